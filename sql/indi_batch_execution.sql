@@ -0,0 +1,32 @@
+CREATE SEQUENCE INDI_BATCH_JOB_EXECUTION_SEQ;
+CREATE SEQUENCE INDI_BATCH_STEP_EXECUTION_SEQ;
+
+CREATE TABLE INDI_BATCH_JOB_EXECUTION (
+    JOB_EXECUTION_ID NUMBER NOT NULL,
+    JOB_NAME VARCHAR2(100) NOT NULL,
+    FLOW_NAME VARCHAR2(255) NOT NULL,
+    SOURCE_FILE VARCHAR2(255),
+    START_TIME TIMESTAMP NOT NULL,
+    END_TIME TIMESTAMP,
+    STATUS VARCHAR2(10) NOT NULL,
+    EXIT_CODE VARCHAR2(20) NOT NULL,
+    EXIT_MESSAGE VARCHAR2(2500),
+    LAST_UPDATED TIMESTAMP NOT NULL,
+    CONSTRAINT INDI_BATCH_JOB_EXECUTION_PK PRIMARY KEY (JOB_EXECUTION_ID)
+);
+
+CREATE TABLE INDI_BATCH_STEP_EXECUTION (
+    STEP_EXECUTION_ID NUMBER NOT NULL,
+    JOB_EXECUTION_ID NUMBER NOT NULL,
+    STEP_NAME VARCHAR2(100) NOT NULL,
+    START_TIME TIMESTAMP NOT NULL,
+    END_TIME TIMESTAMP,
+    STATUS VARCHAR2(10) NOT NULL,
+    READ_COUNT NUMBER NOT NULL,
+    WRITE_COUNT NUMBER NOT NULL,
+    SKIP_COUNT NUMBER NOT NULL,
+    EXIT_MESSAGE VARCHAR2(2500),
+    LAST_UPDATED TIMESTAMP NOT NULL,
+    CONSTRAINT INDI_BATCH_STEP_EXECUTION_PK PRIMARY KEY (STEP_EXECUTION_ID),
+    CONSTRAINT INDI_BATCH_STEP_EXECUTION_FK FOREIGN KEY (JOB_EXECUTION_ID) REFERENCES INDI_BATCH_JOB_EXECUTION (JOB_EXECUTION_ID)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE INDI_SEQUENCE_RESERVATION (
+    SEQUENCE_NAME VARCHAR2(128) NOT NULL,
+    SEQUENCE_VALUE NUMBER NOT NULL,
+    FILE_NAME VARCHAR2(255),
+    STATUS VARCHAR2(16) NOT NULL,
+    RESERVED_AT TIMESTAMP NOT NULL,
+    DELIVERED_AT TIMESTAMP,
+    CONSTRAINT INDI_SEQUENCE_RESERVATION_PK PRIMARY KEY (SEQUENCE_NAME, SEQUENCE_VALUE),
+    CONSTRAINT INDI_SEQUENCE_RESERVATION_CK CHECK (STATUS IN ('RESERVED', 'RELEASED', 'DELIVERED'))
+);
+
+CREATE INDEX INDI_SEQUENCE_RESERVATION_IX ON INDI_SEQUENCE_RESERVATION (SEQUENCE_NAME, STATUS, FILE_NAME);
@@ -0,0 +1,4 @@
+ALTER TABLE OBT_FILE_BLOB ADD (
+    FILE_COMPRESSION VARCHAR2(16),
+    CONSTRAINT OBT_FILE_BLOB_COMPRESSION_CK CHECK (FILE_COMPRESSION IN ('GZIP'))
+);
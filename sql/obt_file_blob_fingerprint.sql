@@ -0,0 +1,6 @@
+ALTER TABLE OBT_FILE_BLOB ADD (
+    FILE_SHA256 VARCHAR2(64),
+    FILE_SEQUENCE NUMBER
+);
+
+CREATE UNIQUE INDEX OBT_FILE_BLOB_FINGERPRINT_UX ON OBT_FILE_BLOB (FLOW_NAME, FILE_SHA256, FILE_NAME, FILE_SEQUENCE);
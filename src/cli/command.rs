@@ -0,0 +1,76 @@
+use std::{fmt, path::PathBuf};
+
+pub static USAGE: &str = "Usage: indi-rust [--config <path>] [--flow <SYSTEM/FLOW>] <command>
+
+Commands:
+  run                     Process sources forever, polling every general.poll_interval seconds
+  once                    Process sources until none are left, then exit (default)
+  dry-run                 Split the oldest queued source of each flow without DB writes, sequence increments or uploads
+  status                  Show the contents of the failure and workspace queues
+  replay <archived-file>  Queue a file from archive/source again and process it
+  help                    Show this message (also -h, --help)
+
+Options:
+  --config <path>         Configuration file (default: INDI_CONFIG or config.toml)
+  --flow <SYSTEM/FLOW>    Restrict the command to a single flow";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Once,
+    DryRun,
+    Status,
+    Replay(PathBuf),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub flow: Option<String>,
+    pub command: Command,
+}
+
+#[derive(Debug)]
+pub struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl Cli {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Cli, CliError> {
+        let mut config = None;
+        let mut flow = None;
+        let mut command = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config = Some(PathBuf::from(option_value(&arg, args.next())?)),
+                "--flow" => flow = Some(option_value(&arg, args.next())?),
+                "-h" | "--help" | "help" => return Ok(Cli {config, flow, command: Command::Help}),
+                _ if command.is_some() => return Err(CliError(format!("unexpected argument `{}`", arg))),
+                "run" => command = Some(Command::Run),
+                "once" => command = Some(Command::Once),
+                "dry-run" => command = Some(Command::DryRun),
+                "status" => command = Some(Command::Status),
+                "replay" => match args.next() {
+                    Some(file) if !file.starts_with("--") => command = Some(Command::Replay(PathBuf::from(file))),
+                    _ => return Err(CliError(String::from("replay requires an archived file"))),
+                },
+                _ => return Err(CliError(format!("unknown command `{}`", arg))),
+            }
+        }
+        Ok(Cli {config, flow, command: command.unwrap_or(Command::Once)})
+    }
+}
+
+fn option_value(option: &str, value: Option<String>) -> Result<String, CliError> {
+    match value {
+        Some(v) if !v.starts_with("--") => Ok(v),
+        _ => Err(CliError(format!("{} requires a value", option))),
+    }
+}
@@ -0,0 +1 @@
+pub mod command;
\ No newline at end of file
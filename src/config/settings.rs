@@ -0,0 +1,579 @@
+use std::{collections::HashSet, env, fmt, fs, path::{Path, PathBuf}, str::FromStr};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use toml::Value;
+
+use crate::layout::encoding;
+use crate::layout::record::{LayoutConfig, MOVEMENT_CODE};
+use crate::layout::terminator::LineTerminator;
+use crate::pipeline::blob::BlobConfig;
+use crate::pipeline::cache::VinCacheConfig;
+use crate::registry::vehicle::RegistryConfig;
+use crate::repository::job::JobRepositoryConfig;
+use crate::routing::table::RoutingConfig;
+use crate::sql_client::sequence;
+
+pub static DEFAULT_CONFIG_PATH: &str = "config.toml";
+pub static CONFIG_PATH_VAR: &str = "INDI_CONFIG";
+pub static OVERRIDE_PREFIX: &str = "INDI__";
+pub static OVERRIDE_SEPARATOR: &str = "__";
+pub static DEFAULT_SFTP_PORT: u16 = 22;
+// Directory names used by the pipeline next to the destination ones
+pub static RESERVED_NAMES: [&str; 2] = ["source", "reject"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Override(String, String),
+    Invalid(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read config file {:?}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config file {:?}: {}", path, e),
+            ConfigError::Override(var, reason) => write!(f, "invalid override {}: {}", var, reason),
+            ConfigError::Invalid(key, reason) => write!(f, "invalid value for key `{}`: {}", key, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub general: GeneralConfig,
+    pub database: DatabasesConfig,
+    #[serde(default = "default_vehicle_registry")]
+    pub vehicle_registry: RegistryConfig,
+    #[serde(default)]
+    pub vin_cache: VinCacheConfig,
+    #[serde(default = "default_job_repository")]
+    pub job_repository: JobRepositoryConfig,
+    pub flows: Vec<FlowConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeneralConfig {
+    pub root: String,
+    #[serde(default = "default_archive")]
+    pub archive: String,
+    #[serde(default = "default_failure")]
+    pub failure: String,
+    #[serde(default = "default_workspace")]
+    pub workspace: String,
+    #[serde(default = "default_rejected")]
+    pub rejected: String,
+    #[serde(default = "default_state")]
+    pub state: String,
+    #[serde(default = "default_poll_interval", deserialize_with = "lenient")]
+    pub poll_interval: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabasesConfig {
+    pub indi: DatabaseConfig,
+    pub obt: DatabaseConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub username: String,
+    pub password: String,
+    pub url: String,
+    #[serde(default = "default_pool_size", deserialize_with = "lenient")]
+    pub pool_size: usize,
+    // Seconds, 0 waits forever
+    #[serde(default = "default_connect_timeout", deserialize_with = "lenient")]
+    pub connect_timeout: u64,
+    #[serde(default = "default_call_timeout", deserialize_with = "lenient")]
+    pub call_timeout: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "default_sftp_port", deserialize_with = "lenient")]
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlowConfig {
+    pub system: String,
+    pub flow: String,
+    pub batch_name: String,
+    pub root: Option<String>,
+    #[serde(default = "default_line_terminator")]
+    pub line_terminator: LineTerminator,
+    pub source: SourceConfig,
+    pub layout: LayoutConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    pub routing: RoutingConfig,
+    pub destinations: Vec<DestinationConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub file: String,
+    pub sftp: SftpConfig,
+    #[serde(deserialize_with = "lenient")]
+    pub sftp_delete_remote: bool,
+    #[serde(deserialize_with = "lenient")]
+    pub sftp_check_lastmtime: bool,
+    #[serde(deserialize_with = "lenient")]
+    pub sftp_lastmtime: u64,
+    pub encoding: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ValidationConfig {
+    #[serde(default, deserialize_with = "lenient")]
+    pub records_number: bool,
+    #[serde(default, deserialize_with = "lenient")]
+    pub sequence_continuity: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DestinationConfig {
+    pub name: String,
+    pub sequence_schema: String,
+    #[serde(default = "default_rewrite", deserialize_with = "lenient")]
+    pub rewrite_header: bool,
+    #[serde(default = "default_rewrite", deserialize_with = "lenient")]
+    pub rewrite_footer: bool,
+    // Output encoding, defaults to the source one
+    pub encoding: Option<String>,
+    pub delivery: DeliveryConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeliveryConfig {
+    Sftp(SftpConfig),
+    Blob(BlobConfig),
+}
+
+fn default_archive() -> String {
+    String::from("archive")
+}
+
+fn default_failure() -> String {
+    String::from("failure")
+}
+
+fn default_workspace() -> String {
+    String::from("workspace")
+}
+
+fn default_rejected() -> String {
+    String::from("rejected")
+}
+
+fn default_state() -> String {
+    String::from("state")
+}
+
+fn default_poll_interval() -> u64 {
+    60
+}
+
+fn default_pool_size() -> usize {
+    2
+}
+
+fn default_connect_timeout() -> u64 {
+    30
+}
+
+fn default_call_timeout() -> u64 {
+    300
+}
+
+fn default_rewrite() -> bool {
+    true
+}
+
+fn default_sftp_port() -> u16 {
+    DEFAULT_SFTP_PORT
+}
+
+fn default_vehicle_registry() -> RegistryConfig {
+    RegistryConfig::Oracle
+}
+
+fn default_job_repository() -> JobRepositoryConfig {
+    JobRepositoryConfig::None
+}
+
+fn default_line_terminator() -> LineTerminator {
+    LineTerminator::Preserve
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(p) => p.to_owned(),
+            None => PathBuf::from(env::var(CONFIG_PATH_VAR).unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())),
+        };
+        Config::from_file(&path)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        let mut value = content.parse::<Value>().map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        apply_overrides(&mut value, env::vars())?;
+        expand_env(&mut value, "")?;
+        let config: Config = value.try_into().map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        config.validate()?;
+        println!("Loaded configuration: {:?} ({} flows)", path, config.flows.len());
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        not_empty("general.root", &self.general.root)?;
+        path_segment("general.archive", &self.general.archive)?;
+        path_segment("general.failure", &self.general.failure)?;
+        path_segment("general.workspace", &self.general.workspace)?;
+        path_segment("general.rejected", &self.general.rejected)?;
+        path_segment("general.state", &self.general.state)?;
+        if self.general.poll_interval == 0 {
+            return Err(invalid("general.poll_interval", "must be greater than 0"));
+        }
+        if let RegistryConfig::File(file) = &self.vehicle_registry {
+            not_empty("vehicle_registry.path", &file.path)?;
+        }
+        if let JobRepositoryConfig::Sqlite {path} = &self.job_repository {
+            not_empty("job_repository.path", path)?;
+        }
+        if self.flows.is_empty() {
+            return Err(invalid("flows", "at least one flow is required"));
+        }
+        let mut names = HashSet::new();
+        for (i, flow) in self.flows.iter().enumerate() {
+            let key = format!("flows[{}]", i);
+            flow.validate(&key)?;
+            if !names.insert((flow.system.as_str(), flow.flow.as_str())) {
+                return Err(invalid(&(key + ".flow"), "duplicated system/flow pair"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FlowConfig {
+    pub fn name(&self) -> String {
+        self.system.to_string() + "/" + &self.flow
+    }
+
+    pub fn root<'a>(&'a self, general: &'a GeneralConfig) -> &'a str {
+        self.root.as_deref().unwrap_or(&general.root)
+    }
+
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        path_segment(&format!("{}.system", key), &self.system)?;
+        path_segment(&format!("{}.flow", key), &self.flow)?;
+        identifier_part(&format!("{}.system", key), &self.system)?;
+        not_empty(&format!("{}.batch_name", key), &self.batch_name)?;
+        if let Some(root) = &self.root {
+            not_empty(&format!("{}.root", key), root)?;
+        }
+        if let Err(e) = Regex::new(&self.source.file) {
+            return Err(invalid(&format!("{}.source.file", key), &e.to_string()));
+        }
+        self.source.sftp.validate(&format!("{}.source.sftp", key))?;
+        if encoding::lookup(&self.source.encoding).is_none() {
+            return Err(invalid(&format!("{}.source.encoding", key), "unknown or unsupported encoding"));
+        }
+        self.layout.validate(&format!("{}.layout", key))?;
+        if self.validation.records_number && self.layout.footer.is_none() {
+            return Err(invalid(&format!("{}.validation.records_number", key), "requires a footer layout"));
+        }
+        if self.validation.sequence_continuity && self.layout.header.is_none() {
+            return Err(invalid(&format!("{}.validation.sequence_continuity", key), "requires a header layout"));
+        }
+        if self.destinations.is_empty() {
+            return Err(invalid(&format!("{}.destinations", key), "at least one destination is required"));
+        }
+        let mut names = HashSet::new();
+        for (i, destination) in self.destinations.iter().enumerate() {
+            let destination_key = format!("{}.destinations[{}]", key, i);
+            destination.validate(&destination_key)?;
+            if !names.insert(destination.name.as_str()) {
+                return Err(invalid(&(destination_key + ".name"), "duplicated destination name"));
+            }
+        }
+        let movement_code = self.layout.body.field(MOVEMENT_CODE).unwrap();
+        let names: Vec<&str> = self.destinations.iter().map(|d| d.name.as_str()).collect();
+        self.routing.validate(&format!("{}.routing", key), movement_code.length, &names)?;
+        Ok(())
+    }
+
+    pub fn output_encoding<'a>(&'a self, destination: &'a DestinationConfig) -> &'a str {
+        destination.encoding.as_deref().unwrap_or(&self.source.encoding)
+    }
+
+    pub fn destination_index(&self, name: &str) -> Option<usize> {
+        self.destinations.iter().position(|d| d.name == name)
+    }
+}
+
+impl DestinationConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        path_segment(&format!("{}.name", key), &self.name)?;
+        if RESERVED_NAMES.contains(&self.name.as_str()) {
+            return Err(invalid(&format!("{}.name", key), "is a reserved name"));
+        }
+        if !sequence::is_identifier(&self.sequence_schema) {
+            return Err(invalid(&format!("{}.sequence_schema", key), "must be an unquoted Oracle identifier (capital letters, digits, _, $ and #, starting with a letter)"));
+        }
+        identifier_part(&format!("{}.name", key), &self.name.to_uppercase())?;
+        if let Some(name) = &self.encoding {
+            if encoding::lookup(name).is_none() {
+                return Err(invalid(&format!("{}.encoding", key), "unknown or unsupported encoding"));
+            }
+        }
+        match &self.delivery {
+            DeliveryConfig::Sftp(sftp) => sftp.validate(&format!("{}.delivery", key)),
+            DeliveryConfig::Blob(blob) => blob.validate(&format!("{}.delivery", key)),
+        }
+    }
+}
+
+impl SftpConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        not_empty(&format!("{}.host", key), &self.host)?;
+        not_empty(&format!("{}.username", key), &self.username)?;
+        not_empty(&format!("{}.path", key), &self.path)?;
+        if self.port == 0 {
+            return Err(invalid(&format!("{}.port", key), "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(key: &str, reason: &str) -> ConfigError {
+    ConfigError::Invalid(key.to_string(), reason.to_string())
+}
+
+fn not_empty(key: &str, value: &str) -> Result<(), ConfigError> {
+    if value.trim().is_empty() {
+        return Err(invalid(key, "must not be empty"));
+    }
+    Ok(())
+}
+
+fn path_segment(key: &str, value: &str) -> Result<(), ConfigError> {
+    not_empty(key, value)?;
+    if value.contains('/') || value.contains('\\') || value == "." || value == ".." {
+        return Err(invalid(key, "must be a single directory name"));
+    }
+    Ok(())
+}
+
+// System and destination names are part of the sequence names
+fn identifier_part(key: &str, value: &str) -> Result<(), ConfigError> {
+    if !value.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "_$#".contains(c)) {
+        return Err(invalid(key, "may only contain capital letters, digits, _, $ and #"));
+    }
+    Ok(())
+}
+
+// INDI__SECTION__KEY=value overrides `key` of table `[section]`, INDI__FLOWS__0__SYSTEM the first flow
+fn apply_overrides<I: Iterator<Item = (String, String)>>(value: &mut Value, vars: I) -> Result<(), ConfigError> {
+    for (var, raw) in vars {
+        let keys = match var.strip_prefix(OVERRIDE_PREFIX) {
+            Some(k) => k.split(OVERRIDE_SEPARATOR).map(|s| s.to_lowercase()).collect::<Vec<String>>(),
+            None => continue,
+        };
+        if keys.iter().any(|k| k.is_empty()) {
+            return Err(ConfigError::Override(var, String::from("empty key segment")));
+        }
+        let (last, parents) = keys.split_last().unwrap();
+        let mut current = &mut *value;
+        for key in parents {
+            current = match current {
+                Value::Table(t) => t.entry(key.to_owned()).or_insert_with(|| Value::Table(toml::value::Table::new())),
+                Value::Array(a) => match key.parse::<usize>().ok().and_then(|i| a.get_mut(i)) {
+                    Some(v) => v,
+                    None => return Err(ConfigError::Override(var, format!("no array element `{}`", key))),
+                },
+                _ => return Err(ConfigError::Override(var, format!("`{}` is not a table", key))),
+            };
+        }
+        match current {
+            Value::Table(t) => {
+                t.insert(last.to_owned(), parse_override(&raw));
+            },
+            _ => return Err(ConfigError::Override(var, String::from("parent is not a table"))),
+        }
+        println!("Config override: {}", keys.join("."));
+    }
+    Ok(())
+}
+
+// Overrides are inserted as strings, the numeric and boolean keys read them through lenient;
+// arrays and inline tables are read as TOML literals
+fn parse_override(raw: &str) -> Value {
+    match format!("v = {}", raw).parse::<Value>() {
+        Ok(Value::Table(mut t)) => match t.remove("v") {
+            Some(v) if v.is_array() || v.is_table() => v,
+            _ => Value::String(raw.to_string()),
+        },
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+// Numeric and boolean keys also accept their value as a string, as set by an override
+pub fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error> where D: Deserializer<'de>, T: Deserialize<'de> + FromStr, T::Err: fmt::Display {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Lenient<T> {
+        Value(T),
+        Text(String),
+    }
+    match Lenient::<T>::deserialize(deserializer)? {
+        Lenient::Value(value) => Ok(value),
+        Lenient::Text(text) => text.trim().parse().map_err(|e| de::Error::custom(format!("{:?}: {}", text, e))),
+    }
+}
+
+// Replaces ${VAR} references in string values with the environment variable content
+fn expand_env(value: &mut Value, key: &str) -> Result<(), ConfigError> {
+    match value {
+        Value::String(s) => {
+            let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+            let mut expanded = String::new();
+            let mut last = 0;
+            for caps in re.captures_iter(s) {
+                let m = caps.get(0).unwrap();
+                let var = &caps[1];
+                match env::var(var) {
+                    Ok(v) => {
+                        expanded.push_str(&s[last..m.start()]);
+                        expanded.push_str(&v);
+                        last = m.end();
+                    },
+                    Err(_) => return Err(invalid(key, &format!("environment variable {} is not set", var))),
+                }
+            }
+            expanded.push_str(&s[last..]);
+            *s = expanded;
+        },
+        Value::Table(t) => {
+            for (k, v) in t.iter_mut() {
+                let child = if key.is_empty() { k.to_owned() } else { format!("{}.{}", key, k) };
+                expand_env(v, &child)?;
+            }
+        },
+        Value::Array(a) => {
+            for (i, v) in a.iter_mut().enumerate() {
+                expand_env(v, &format!("{}[{}]", key, i))?;
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Value {
+        include_str!("../../config.toml").parse::<Value>().unwrap()
+    }
+
+    fn vars(overrides: &[(&str, &str)]) -> std::vec::IntoIter<(String, String)> {
+        overrides.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<(String, String)>>().into_iter()
+    }
+
+    #[test]
+    fn keeps_scalar_overrides_as_strings() {
+        for raw in ["123456", "007", "1e3", "true", "\"quoted\"", "[unclosed", ""].iter() {
+            assert_eq!(parse_override(raw), Value::String(raw.to_string()));
+        }
+    }
+
+    #[test]
+    fn reads_arrays_and_tables_as_literals() {
+        assert_eq!(parse_override("[\"A\", \"B\"]"), Value::Array(vec![Value::String(String::from("A")), Value::String(String::from("B"))]));
+        assert_eq!(parse_override("{ type = \"memory\" }").get("type"), Some(&Value::String(String::from("memory"))));
+    }
+
+    #[test]
+    fn applies_overrides_by_path() {
+        let mut value = sample();
+        apply_overrides(&mut value, vars(&[("INDI__GENERAL__ROOT", "/data"), ("INDI__FLOWS__0__SOURCE__SFTP_LASTMTIME", "60"), ("INDI__NEW__KEY", "x"), ("OTHER__GENERAL__ROOT", "/ignored")])).unwrap();
+        assert_eq!(value["general"]["root"].as_str(), Some("/data"));
+        assert_eq!(value["flows"][0]["source"]["sftp_lastmtime"].as_str(), Some("60"));
+        assert_eq!(value["new"]["key"].as_str(), Some("x"));
+    }
+
+    #[test]
+    fn rejects_invalid_override_paths() {
+        let mut value = sample();
+        match apply_overrides(&mut value, vars(&[("INDI__FLOWS__1__SYSTEM", "X")])) {
+            Err(ConfigError::Override(var, _)) => assert_eq!(var, "INDI__FLOWS__1__SYSTEM"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(apply_overrides(&mut value, vars(&[("INDI__GENERAL____ROOT", "/data")])).is_err());
+        assert!(apply_overrides(&mut value, vars(&[("INDI__GENERAL__ROOT__PATH", "/data")])).is_err());
+    }
+
+    #[test]
+    fn converts_overrides_to_the_key_type() {
+        let mut value = sample();
+        value["database"]["indi"].as_table_mut().unwrap().remove("password");
+        apply_overrides(&mut value, vars(&[
+            ("INDI__DATABASE__INDI__PASSWORD", "123456"),
+            ("INDI__DATABASE__INDI__POOL_SIZE", "3"),
+            ("INDI__GENERAL__POLL_INTERVAL", "007"),
+            ("INDI__FLOWS__0__ROOT", "1e3"),
+            ("INDI__FLOWS__0__SOURCE__SFTP_DELETE_REMOTE", "false"),
+            ("INDI__FLOWS__0__DESTINATIONS__1__ENCODING", "true"),
+            ("INDI__FLOWS__0__DESTINATIONS__1__DELIVERY__CHUNK_SIZE", "4096"),
+            ("INDI__VIN_CACHE__PRELOAD", "true"),
+            ("INDI__VEHICLE_REGISTRY", "{ type = \"file\", path = \"vins.csv\" }"),
+            ("INDI__VEHICLE_REGISTRY__COLUMN", "2"),
+        ])).unwrap();
+        let config: Config = value.try_into().unwrap();
+        assert_eq!(config.database.indi.password, "123456");
+        assert_eq!(config.database.indi.pool_size, 3);
+        assert_eq!(config.general.poll_interval, 7);
+        assert_eq!(config.flows[0].root.as_deref(), Some("1e3"));
+        assert!(!config.flows[0].source.sftp_delete_remote);
+        assert_eq!(config.flows[0].destinations[1].encoding.as_deref(), Some("true"));
+        match &config.flows[0].destinations[1].delivery {
+            DeliveryConfig::Blob(blob) => assert_eq!(blob.chunk_size, 4096),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(config.vin_cache.preload);
+        match &config.vehicle_registry {
+            RegistryConfig::File(file) => assert_eq!(file.column, 2),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_overrides_of_the_wrong_type() {
+        let mut value = sample();
+        apply_overrides(&mut value, vars(&[("INDI__DATABASE__INDI__POOL_SIZE", "two")])).unwrap();
+        let e = value.try_into::<Config>().unwrap_err().to_string();
+        assert!(e.contains("\"two\"") && e.contains("database.indi.pool_size"), "{}", e);
+    }
+}
@@ -0,0 +1,23 @@
+use encoding_rs::Encoding;
+
+// Accepts Java/Oracle style names such as UTF_8, ISO8859_1 or Cp1252 besides the WHATWG labels,
+// only ASCII compatible encodings can be split on line feeds and written back
+pub fn lookup(name: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(name.trim().replace('_', "-").as_bytes())
+        .filter(|e| e.is_ascii_compatible() && e.output_encoding() == *e)
+}
+
+pub fn decode(encoding: &'static Encoding, bytes: &[u8]) -> Result<String, String> {
+    match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+        Some(line) => Ok(line.into_owned()),
+        None => Err(format!("malformed {} sequence", encoding.name())),
+    }
+}
+
+pub fn encode(encoding: &'static Encoding, line: &str) -> Result<Vec<u8>, String> {
+    let (bytes, _, unmappable) = encoding.encode(line);
+    if unmappable {
+        return Err(format!("characters not representable in {}", encoding.name()));
+    }
+    Ok(bytes.into_owned())
+}
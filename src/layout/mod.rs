@@ -0,0 +1,3 @@
+pub mod encoding;
+pub mod record;
+pub mod terminator;
\ No newline at end of file
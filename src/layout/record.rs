@@ -0,0 +1,274 @@
+use std::{collections::{HashMap, HashSet}, fmt, ops::Range};
+use serde::Deserialize;
+
+use crate::config::settings::{lenient, ConfigError};
+
+pub static SEQUENCE: &str = "sequence";
+pub static RECORDS_NUMBER: &str = "records_number";
+pub static MOVEMENT_CODE: &str = "movement_code";
+pub static VIN: &str = "vin";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutConfig {
+    pub header: Option<RecordLayout>,
+    pub body: RecordLayout,
+    pub footer: Option<RecordLayout>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordLayout {
+    pub marker: Option<RecordMarker>,
+    pub fields: Vec<FieldLayout>,
+}
+
+// Record type value found at offset in every record of this type
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordMarker {
+    #[serde(deserialize_with = "lenient")]
+    pub offset: usize,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldLayout {
+    pub name: String,
+    #[serde(deserialize_with = "lenient")]
+    pub offset: usize,
+    #[serde(deserialize_with = "lenient")]
+    pub length: usize,
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    Alphanumeric,
+    Numeric,
+}
+
+// Field that cannot be read from or written into a line
+#[derive(Debug)]
+pub struct FieldError {
+    pub line: usize,
+    pub field: String,
+    pub expected_length: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, field {} ({} characters): {}", self.line, self.field, self.expected_length, self.reason)
+    }
+}
+
+pub struct Record<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Record<'a> {
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.values.get(name).copied()
+    }
+
+    pub fn number(&self, name: &str) -> Option<u64> {
+        self.get(name).and_then(|v| v.trim().parse::<u64>().ok())
+    }
+}
+
+impl LayoutConfig {
+    pub fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        if let Some(header) = &self.header {
+            header.validate(&format!("{}.header", key), &[SEQUENCE])?;
+        }
+        self.body.validate(&format!("{}.body", key), &[MOVEMENT_CODE, VIN])?;
+        if self.body.marker.is_some() {
+            return Err(ConfigError::Invalid(format!("{}.body.marker", key), String::from("only header and footer records have a marker")));
+        }
+        if let Some(footer) = &self.footer {
+            footer.validate(&format!("{}.footer", key), &[SEQUENCE, RECORDS_NUMBER])?;
+        }
+        Ok(())
+    }
+}
+
+impl RecordLayout {
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    // Records without marker always match
+    pub fn matches(&self, line: &str) -> bool {
+        match &self.marker {
+            Some(m) => m.matches(line),
+            None => true,
+        }
+    }
+
+    // Without marker the footer is the last line
+    pub fn is_footer(&self, line: &str, last: bool) -> bool {
+        if self.marker.is_some() { self.matches(line) } else { last }
+    }
+
+    // Fails on the first field that does not fit in the line
+    pub fn parse<'a>(&'a self, number: usize, line: &'a str) -> Result<Record<'a>, FieldError> {
+        let mut values = HashMap::new();
+        for field in &self.fields {
+            let range = field.range(number, line)?;
+            values.insert(field.name.as_str(), &line[range]);
+        }
+        Ok(Record {values})
+    }
+
+    // Returns the line with the named field replaced by value, padded to the field length
+    pub fn set(&self, number: usize, line: &str, name: &str, value: &str) -> Result<String, FieldError> {
+        let field = match self.field(name) {
+            Some(f) => f,
+            None => return Err(FieldError {line: number, field: name.to_string(), expected_length: 0, reason: String::from("is not defined in the layout")}),
+        };
+        let formatted = field.format(value).map_err(|reason| field.error(number, reason))?;
+        let range = field.range(number, line)?;
+        let mut new_line = String::from(line);
+        new_line.replace_range(range, &formatted);
+        Ok(new_line)
+    }
+
+    fn validate(&self, key: &str, required: &[&str]) -> Result<(), ConfigError> {
+        if let Some(marker) = &self.marker {
+            if marker.value.is_empty() {
+                return Err(ConfigError::Invalid(format!("{}.marker.value", key), String::from("must not be empty")));
+            }
+        }
+        let mut names = HashSet::new();
+        for (i, field) in self.fields.iter().enumerate() {
+            let field_key = format!("{}.fields[{}]", key, i);
+            if field.name.trim().is_empty() {
+                return Err(ConfigError::Invalid(field_key + ".name", String::from("must not be empty")));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(ConfigError::Invalid(field_key + ".name", format!("duplicated field {}", field.name)));
+            }
+            if field.length == 0 {
+                return Err(ConfigError::Invalid(field_key + ".length", String::from("must be greater than 0")));
+            }
+        }
+        for name in required {
+            if !names.contains(name) {
+                return Err(ConfigError::Invalid(key.to_string() + ".fields", format!("field {} is required", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RecordMarker {
+    pub fn matches(&self, line: &str) -> bool {
+        char_range(line, self.offset, self.value.chars().count()).map(|r| &line[r]) == Some(self.value.as_str())
+    }
+}
+
+impl FieldLayout {
+    fn range(&self, number: usize, line: &str) -> Result<Range<usize>, FieldError> {
+        match char_range(line, self.offset, self.length) {
+            Some(r) => Ok(r),
+            None => Err(self.error(number, format!("offset {} is out of line bounds ({} characters)", self.offset, line.chars().count()))),
+        }
+    }
+
+    fn error(&self, number: usize, reason: String) -> FieldError {
+        FieldError {line: number, field: self.name.to_string(), expected_length: self.length, reason}
+    }
+
+    pub fn format(&self, value: &str) -> Result<String, String> {
+        if value.chars().count() > self.length {
+            return Err(format!("value {:?} does not fit field {} of length {}", value, self.name, self.length));
+        }
+        match self.kind {
+            FieldType::Numeric if !value.chars().all(|c| c.is_ascii_digit()) => Err(format!("value {:?} is not numeric for field {}", value, self.name)),
+            FieldType::Numeric => Ok(format!("{:0>width$}", value, width = self.length)),
+            FieldType::Alphanumeric => Ok(format!("{:<width$}", value, width = self.length)),
+        }
+    }
+}
+
+// Offsets and lengths count decoded characters, returns the matching byte range of the line
+fn char_range(line: &str, offset: usize, length: usize) -> Option<Range<usize>> {
+    let mut bounds = line.char_indices().map(|(i, _)| i).chain(std::iter::once(line.len()));
+    let start = bounds.nth(offset)?;
+    let end = if length == 0 { start } else { bounds.nth(length - 1)? };
+    Some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout() -> RecordLayout {
+        toml::from_str(r#"
+            marker = { offset = 0, value = "TR" }
+            fields = [
+                { name = "code", offset = 2, length = 3, type = "alphanumeric" },
+                { name = "records_number", offset = 5, length = 4, type = "numeric" },
+            ]
+        "#).unwrap()
+    }
+
+    #[test]
+    fn parses_fields() {
+        let layout = layout();
+        let record = layout.parse(3, "TRAB 0012rest").unwrap();
+        assert_eq!(record.get("code"), Some("AB "));
+        assert_eq!(record.number(RECORDS_NUMBER), Some(12));
+        assert_eq!(record.get("other"), None);
+    }
+
+    #[test]
+    fn rejects_short_lines() {
+        let layout = layout();
+        let e = layout.parse(7, "TRAB 001").err().unwrap();
+        assert_eq!((e.line, e.field.as_str(), e.expected_length), (7, RECORDS_NUMBER, 4));
+        assert_eq!(e.to_string(), "line 7, field records_number (4 characters): offset 5 is out of line bounds (8 characters)");
+        assert!(layout.parse(1, "").is_err());
+        assert!(!layout.matches("T"));
+        // A field ending with the line fits
+        assert!(layout.parse(1, "TRAB 0012").is_ok());
+    }
+
+    #[test]
+    fn counts_offsets_in_characters() {
+        let layout = layout();
+        let record = layout.parse(1, "TRÉÀÜ0012").unwrap();
+        assert_eq!(record.get("code"), Some("ÉÀÜ"));
+        assert_eq!(record.get(RECORDS_NUMBER), Some("0012"));
+        // The field starts after multi-byte characters whose bytes would overrun the line
+        assert!(layout.parse(1, "TRÉÀÜ001").is_err());
+        assert!(layout.matches("TRé"));
+        assert!(!layout.matches("ÉTR"));
+        assert_eq!(char_range("aéb", 1, 1), Some(1..3));
+        assert_eq!(char_range("aéb", 2, 1), Some(3..4));
+        assert_eq!(char_range("aéb", 3, 1), None);
+    }
+
+    #[test]
+    fn sets_padded_values() {
+        let layout = layout();
+        assert_eq!(layout.set(1, "TRÉÀÜ0012x", RECORDS_NUMBER, "7").unwrap(), "TRÉÀÜ0007x");
+        assert_eq!(layout.set(1, "TRÉÀÜ0012x", "code", "é").unwrap(), "TRé  0012x");
+        assert!(layout.set(1, "TRAB 0012", "missing", "1").is_err());
+        assert!(layout.set(1, "TRAB 0012", RECORDS_NUMBER, "1A").is_err());
+        assert!(layout.set(1, "TRAB 001", RECORDS_NUMBER, "1").is_err());
+    }
+
+    #[test]
+    fn rejects_values_that_do_not_fit() {
+        let layout = layout();
+        let e = layout.set(9, "TRAB 9999", RECORDS_NUMBER, "10000").err().unwrap();
+        assert_eq!(e.line, 9);
+        assert_eq!(e.reason, "value \"10000\" does not fit field records_number of length 4");
+        assert!(layout.set(9, "TRAB 9999", "code", "ABCD").is_err());
+    }
+}
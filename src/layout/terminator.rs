@@ -0,0 +1,40 @@
+use serde::Deserialize;
+
+pub static UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// Line terminator written in downloaded, split and delivered files,
+// a last line without terminator is left without one
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineTerminator {
+    Preserve,
+    Lf,
+    Crlf,
+}
+
+impl LineTerminator {
+    // Splits a line read up to its line feed into content and original terminator
+    pub fn split(line: &[u8]) -> (&[u8], &[u8]) {
+        if line.ends_with(b"\r\n") {
+            line.split_at(line.len() - 2)
+        } else if line.ends_with(b"\n") {
+            line.split_at(line.len() - 1)
+        } else {
+            (line, &[])
+        }
+    }
+
+    pub fn apply<'a>(&self, original: &'a [u8]) -> &'a [u8] {
+        match self {
+            _ if original.is_empty() => original,
+            LineTerminator::Preserve => original,
+            LineTerminator::Lf => b"\n",
+            LineTerminator::Crlf => b"\r\n",
+        }
+    }
+
+    pub fn convert(&self, line: &[u8]) -> Vec<u8> {
+        let (content, original) = LineTerminator::split(line);
+        [content, self.apply(original)].concat()
+    }
+}
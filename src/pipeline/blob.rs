@@ -0,0 +1,137 @@
+use std::io::{self, Read, Write};
+use flate2::write::GzEncoder;
+use oracle::{Error, sql_type::{Blob, Lob}};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::settings::{lenient, ConfigError};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlobConfig {
+    // Bytes written per call, rounded up to a multiple of the LOB chunk size
+    #[serde(default = "default_chunk_size", deserialize_with = "lenient")]
+    pub chunk_size: usize,
+    #[serde(default = "default_compression")]
+    pub compression: Compression,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    None,
+    Gzip,
+}
+
+// Sizes of a file streamed into a BLOB: read bytes, rows and bytes stored after compression
+pub struct BlobContent {
+    pub length: u64,
+    pub rows: u64,
+    pub stored: u64,
+}
+
+fn default_chunk_size() -> usize {
+    1024 * 1024
+}
+
+fn default_compression() -> Compression {
+    Compression::None
+}
+
+impl BlobConfig {
+    pub fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        if self.chunk_size == 0 {
+            return Err(ConfigError::Invalid(format!("{}.chunk_size", key), String::from("must be greater than 0")));
+        }
+        Ok(())
+    }
+}
+
+impl Compression {
+    // Value of the FILE_COMPRESSION column
+    pub fn column(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("GZIP"),
+        }
+    }
+}
+
+// Buffers writes so that the BLOB only receives whole chunks, except the last one
+struct ChunkWriter<'a> {
+    blob: &'a mut Blob,
+    chunk: Vec<u8>,
+    size: usize,
+    stored: u64,
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.size - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        if self.chunk.len() == self.size {
+            self.flush()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.chunk.is_empty() {
+            self.blob.write_all(&self.chunk)?;
+            self.stored += self.chunk.len() as u64;
+            self.chunk.clear();
+        }
+        Ok(())
+    }
+}
+
+// Streams the bytes of reader into the opened blob, compressed according to config
+pub fn write<R: Read>(blob: &mut Blob, reader: R, config: &BlobConfig) -> Result<BlobContent, Error> {
+    let lob_chunk_size = blob.chunk_size()?.max(1);
+    let size = (config.chunk_size.saturating_sub(1) / lob_chunk_size + 1) * lob_chunk_size;
+    let mut writer = ChunkWriter {blob, chunk: Vec::with_capacity(size), size, stored: 0};
+    let (length, rows) = match config.compression {
+        Compression::None => copy(reader, &mut writer, size).map_err(io_error)?,
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(&mut writer, flate2::Compression::default());
+            let counts = copy(reader, &mut encoder, size).map_err(io_error)?;
+            encoder.finish().map_err(io_error)?;
+            counts
+        },
+    };
+    writer.flush().map_err(io_error)?;
+    Ok(BlobContent {length, rows, stored: writer.stored})
+}
+
+// Counts bytes and rows, a last line without terminator is a row too
+fn copy<R: Read, W: Write>(mut reader: R, writer: &mut W, size: usize) -> io::Result<(u64, u64)> {
+    let mut buf = vec![0; size];
+    let (mut length, mut rows, mut last) = (0, 0, b'\n');
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        length += n as u64;
+        rows += buf[..n].iter().filter(|b| **b == b'\n').count() as u64;
+        last = buf[n - 1];
+    }
+    if last != b'\n' {
+        rows += 1;
+    }
+    Ok((length, rows))
+}
+
+// Hex SHA-256 of the file content, used to recognize files already inserted
+pub fn sha256<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn io_error(e: io::Error) -> Error {
+    Error::InternalError(format!("BLOB content: {}", e))
+}
@@ -0,0 +1,217 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, time::{Duration, Instant}};
+use serde::Deserialize;
+
+use crate::config::settings::lenient;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VinCacheConfig {
+    // Cached VINs, also the largest OBT_VEHICLES set that can be preloaded; 0 disables the cache
+    #[serde(default = "default_capacity", deserialize_with = "lenient")]
+    pub capacity: usize,
+    // Seconds a cached or preloaded membership stays valid
+    #[serde(default = "default_ttl", deserialize_with = "lenient")]
+    pub ttl: u64,
+    #[serde(default, deserialize_with = "lenient")]
+    pub preload: bool,
+}
+
+impl Default for VinCacheConfig {
+    fn default() -> Self {
+        VinCacheConfig {capacity: default_capacity(), ttl: default_ttl(), preload: false}
+    }
+}
+
+fn default_capacity() -> usize {
+    100000
+}
+
+fn default_ttl() -> u64 {
+    3600
+}
+
+struct CacheEntry {
+    exists: bool,
+    inserted: Instant,
+    used: u64,
+}
+
+// VIN -> OBT membership kept across sources, least recently used entries are evicted beyond capacity
+pub struct VinCache {
+    pub capacity: usize,
+    ttl: Duration,
+    preload: bool,
+    entries: HashMap<String, CacheEntry>,
+    usage: BTreeMap<u64, String>,
+    tick: u64,
+    preloaded: Option<HashSet<String>>,
+    preloaded_at: Option<Instant>,
+    hits: u64,
+    misses: u64,
+}
+
+impl VinCache {
+    pub fn new(config: &VinCacheConfig) -> VinCache {
+        VinCache {
+            capacity: config.capacity,
+            ttl: Duration::from_secs(config.ttl),
+            preload: config.preload,
+            entries: HashMap::new(),
+            usage: BTreeMap::new(),
+            tick: 0,
+            preloaded: None,
+            preloaded_at: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, vin: &str) -> Option<bool> {
+        // A valid preloaded set holds every OBT VIN, so it answers for missing ones too
+        if let (Some(preloaded), Some(at)) = (&self.preloaded, self.preloaded_at) {
+            if at.elapsed() < self.ttl {
+                self.hits += 1;
+                return Some(preloaded.contains(vin));
+            }
+        }
+        match self.entries.get_mut(vin) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => {
+                self.usage.remove(&entry.used);
+                self.tick += 1;
+                entry.used = self.tick;
+                self.usage.insert(self.tick, vin.to_string());
+                self.hits += 1;
+                return Some(entry.exists);
+            },
+            Some(_) => self.remove(vin),
+            None => (),
+        }
+        self.misses += 1;
+        None
+    }
+
+    pub fn insert(&mut self, vin: &str, exists: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(vin);
+        while self.entries.len() >= self.capacity {
+            let used = *self.usage.keys().next().unwrap();
+            let evicted = self.usage.remove(&used).unwrap();
+            self.entries.remove(&evicted);
+        }
+        self.tick += 1;
+        self.usage.insert(self.tick, vin.to_string());
+        self.entries.insert(vin.to_string(), CacheEntry {exists, inserted: Instant::now(), used: self.tick});
+    }
+
+    fn remove(&mut self, vin: &str) {
+        if let Some(entry) = self.entries.remove(vin) {
+            self.usage.remove(&entry.used);
+        }
+    }
+
+    pub fn needs_preload(&self) -> bool {
+        match self.preloaded_at {
+            Some(at) => self.preload && at.elapsed() >= self.ttl,
+            None => self.preload,
+        }
+    }
+
+    // None when OBT_VEHICLES could not be preloaded, retried after the ttl
+    pub fn set_preloaded(&mut self, vins: Option<HashSet<String>>) {
+        self.preloaded = vins;
+        self.preloaded_at = Some(Instant::now());
+    }
+
+    // Hits and misses since the last call
+    pub fn take_counts(&mut self) -> (u64, u64) {
+        let counts = (self.hits, self.misses);
+        self.hits = 0;
+        self.misses = 0;
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    fn new_cache(capacity: usize, ttl: u64, preload: bool) -> VinCache {
+        VinCache::new(&VinCacheConfig {capacity, ttl, preload})
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut cache = new_cache(2, 3600, false);
+        cache.insert("A", true);
+        cache.insert("B", false);
+        assert_eq!(cache.get("A"), Some(true));
+        cache.insert("C", true);
+        assert_eq!(cache.get("B"), None);
+        assert_eq!(cache.get("A"), Some(true));
+        assert_eq!(cache.get("C"), Some(true));
+        cache.insert("D", false);
+        assert_eq!(cache.get("A"), None);
+        assert_eq!(cache.get("C"), Some(true));
+        assert_eq!(cache.get("D"), Some(false));
+        assert_eq!(cache.take_counts(), (5, 2));
+        assert_eq!(cache.take_counts(), (0, 0));
+    }
+
+    #[test]
+    fn replaces_an_existing_entry() {
+        let mut cache = new_cache(2, 3600, false);
+        cache.insert("A", false);
+        cache.insert("A", true);
+        cache.insert("B", true);
+        assert_eq!(cache.get("A"), Some(true));
+        assert_eq!(cache.get("B"), Some(true));
+    }
+
+    #[test]
+    fn expires_entries_after_the_ttl() {
+        let mut cache = new_cache(10, 1, false);
+        cache.insert("A", true);
+        assert_eq!(cache.get("A"), Some(true));
+        thread::sleep(Duration::from_millis(1100));
+        assert_eq!(cache.get("A"), None);
+        cache.insert("A", false);
+        assert_eq!(cache.get("A"), Some(false));
+    }
+
+    #[test]
+    fn caches_nothing_without_capacity() {
+        let mut cache = new_cache(0, 3600, false);
+        cache.insert("A", true);
+        assert_eq!(cache.get("A"), None);
+        assert_eq!(cache.take_counts(), (0, 1));
+    }
+
+    #[test]
+    fn answers_misses_from_the_preloaded_set() {
+        let mut cache = new_cache(10, 1, true);
+        assert!(cache.needs_preload());
+        cache.set_preloaded(Some(["A".to_string()].iter().cloned().collect()));
+        assert!(!cache.needs_preload());
+        assert_eq!(cache.get("A"), Some(true));
+        assert_eq!(cache.get("B"), Some(false));
+        assert_eq!(cache.take_counts(), (2, 0));
+        thread::sleep(Duration::from_millis(1100));
+        assert!(cache.needs_preload());
+        assert_eq!(cache.get("B"), None);
+    }
+
+    #[test]
+    fn retries_a_failed_preload_after_the_ttl() {
+        let mut cache = new_cache(10, 1, true);
+        cache.set_preloaded(None);
+        assert!(!cache.needs_preload());
+        assert_eq!(cache.get("A"), None);
+        thread::sleep(Duration::from_millis(1100));
+        assert!(cache.needs_preload());
+        assert!(!new_cache(10, 1, false).needs_preload());
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fs, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline::destination::SplitFile;
+
+// Progress of a source through the work loop, a source in failure/source without checkpoint is downloaded
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    Downloaded,
+    Split,
+    Archived,
+    Queued,
+    Delivered,
+}
+
+// Saved in state/source/<source>.toml after each completed step, so that a restart resumes the source there
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Checkpoint {
+    // Name in failure/source: <download timestamp>_<remote name>
+    pub source: String,
+    pub state: FileState,
+    pub reject_path: Option<PathBuf>,
+    #[serde(default)]
+    pub rejected: u64,
+    #[serde(default)]
+    pub reservations: Vec<Reservation>,
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Reservation {
+    pub sequence: String,
+    pub value: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Output {
+    pub path: PathBuf,
+    pub lines: u64,
+}
+
+impl Checkpoint {
+    pub fn new(source: &str) -> Checkpoint {
+        Checkpoint {source: source.to_string(), state: FileState::Downloaded, reject_path: None, rejected: 0, reservations: Vec::new(), outputs: Vec::new()}
+    }
+
+    // Checkpoints of the flow, oldest source first
+    pub fn all(dir: &Path) -> Vec<Checkpoint> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|f| f.unwrap().path()).filter(|p| p.extension().filter(|e| *e == "toml").is_some()).collect();
+        files.sort();
+        files.iter().map(|f| match toml::from_str(&fs::read_to_string(f).unwrap()) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => panic!("Cannot read checkpoint {:?}! {}", f, e),
+        }).collect()
+    }
+
+    // Source split but not yet queued, resumed before any other source
+    pub fn pending(dir: &Path) -> Option<Checkpoint> {
+        Checkpoint::all(dir).into_iter().find(|c| c.state == FileState::Split || c.state == FileState::Archived)
+    }
+
+    pub fn sequences(&self) -> Vec<(String, u64)> {
+        self.reservations.iter().map(|r| (r.sequence.to_string(), r.value)).collect()
+    }
+
+    pub fn split_files(&self) -> Vec<SplitFile> {
+        self.outputs.iter().map(|o| (o.path.to_owned(), o.lines)).collect()
+    }
+
+    pub fn set_split(&mut self, sequences: &[(String, u64)], outputs: &[SplitFile], reject: &SplitFile) {
+        self.reservations = sequences.iter().map(|(sequence, value)| Reservation {sequence: sequence.to_string(), value: *value}).collect();
+        self.outputs = outputs.iter().map(|(path, lines)| Output {path: path.to_owned(), lines: *lines}).collect();
+        self.reject_path = Some(reject.0.to_owned());
+        self.rejected = reject.1;
+    }
+
+    // Written to a temporary file first, so that a crash never leaves a truncated checkpoint
+    pub fn save(&mut self, dir: &Path, state: FileState) {
+        self.state = state;
+        let path = dir.join(self.source.to_string() + ".toml");
+        let tmp = dir.join(self.source.to_string() + ".tmp");
+        fs::write(&tmp, toml::to_string(self).unwrap()).unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        println!("Checkpoint {}: {:?}", self.source, state);
+    }
+
+    // Delivered sources need no checkpoint anymore
+    pub fn deliver(mut self, dir: &Path) {
+        self.state = FileState::Delivered;
+        fs::remove_file(dir.join(self.source.to_string() + ".toml")).unwrap();
+        println!("Checkpoint {}: {:?}, removed", self.source, self.state);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::config::settings::DestinationConfig;
+use crate::layout::encoding;
+use crate::layout::record::{RecordLayout, RECORDS_NUMBER, SEQUENCE};
+use crate::layout::terminator::{LineTerminator, UTF8_BOM};
+
+pub struct DestinationPaths {
+    pub workspace: PathBuf,
+    pub archive: PathBuf,
+    pub failure: PathBuf,
+}
+
+// Split file path and the number of body records written in it
+pub type SplitFile = (PathBuf, u64);
+
+// Output file of a destination while the source is being split
+pub struct DestinationWriter<'a> {
+    pub config: &'a DestinationConfig,
+    pub path: PathBuf,
+    pub lines: u64,
+    sequence: String,
+    encoding: &'static Encoding,
+    terminator: LineTerminator,
+    writer: BufWriter<File>,
+}
+
+impl<'a> DestinationWriter<'a> {
+    pub fn create(config: &'a DestinationConfig, workspace: &Path, sequence: String, encoding: &'static Encoding, terminator: LineTerminator) -> DestinationWriter<'a> {
+        let path = temp_path(workspace, &config.name);
+        println!("Temp {} file: {:?}", config.name, path);
+        let writer = BufWriter::new(File::create(&path).unwrap());
+        DestinationWriter {config, path, lines: 0, sequence, encoding, terminator, writer}
+    }
+
+    // Only UTF-8 outputs get the BOM of a UTF-8 source
+    pub fn write_bom(&mut self) {
+        if self.encoding == UTF_8 {
+            self.writer.write_all(UTF8_BOM).unwrap();
+        }
+    }
+
+    pub fn write_header(&mut self, header: &RecordLayout, number: usize, line: &str, terminator: &[u8]) -> Result<(), String> {
+        let header = if self.config.rewrite_header {
+            header.set(number, line, SEQUENCE, &self.sequence).map_err(|e| format!("cannot rewrite {} header: {}", self.config.name, e))?
+        } else {
+            line.to_string()
+        };
+        self.write_line(&header, terminator)
+    }
+
+    // Fails when the sequence or the records number no longer fit their footer fields
+    pub fn write_footer(&mut self, footer: &RecordLayout, number: usize, line: &str, terminator: &[u8]) -> Result<(), String> {
+        let footer = if self.config.rewrite_footer {
+            let footer_line = footer.set(number, line, SEQUENCE, &self.sequence).map_err(|e| format!("cannot rewrite {} footer: {}", self.config.name, e))?;
+            footer.set(number, &footer_line, RECORDS_NUMBER, &self.lines.to_string()).map_err(|e| format!("cannot write {} records in {} footer: {}", self.lines, self.config.name, e))?
+        } else {
+            line.to_string()
+        };
+        self.write_line(&footer, terminator)
+    }
+
+    pub fn write_record(&mut self, line: &str, terminator: &[u8]) -> Result<(), String> {
+        self.write_line(line, terminator)?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str, terminator: &[u8]) -> Result<(), String> {
+        let bytes = encoding::encode(self.encoding, line).map_err(|e| format!("cannot write {} record: {}", self.config.name, e))?;
+        self.writer.write_all(&bytes).unwrap();
+        self.writer.write_all(self.terminator.apply(terminator)).unwrap();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> SplitFile {
+        self.writer.flush().unwrap();
+        (self.path, self.lines)
+    }
+}
+
+pub fn temp_path(workspace: &Path, kind: &str) -> PathBuf {
+    let mut path = workspace.to_owned();
+    path.push(kind.to_string() + "_tmp");
+    path
+}
@@ -0,0 +1,783 @@
+use std::{cell::RefCell, collections::HashSet, fs::{File, self, rename, remove_file, remove_dir_all}, io::{BufReader, BufRead}, path::{Path, PathBuf}, time::SystemTime};
+use oracle::{Connection, sql_type::{Timestamp, Blob, Lob}};
+use chrono::{Datelike, Timelike, DateTime, Utc};
+use encoding_rs::UTF_8;
+use regex::Regex;
+use remotefs::RemoteFs;
+use remotefs_ssh::SftpFs;
+
+use crate::config::settings::{DeliveryConfig, DestinationConfig, FlowConfig, GeneralConfig, SourceConfig};
+use crate::layout::encoding;
+use crate::layout::record::{MOVEMENT_CODE, VIN};
+use crate::layout::terminator::{LineTerminator, UTF8_BOM};
+use crate::pipeline::blob::{self, BlobConfig, io_error};
+use crate::pipeline::cache::VinCache;
+use crate::pipeline::checkpoint::{Checkpoint, FileState};
+use crate::pipeline::destination::{DestinationPaths, DestinationWriter, SplitFile};
+use crate::pipeline::router::{Route, Router};
+use crate::pipeline::reject::{RejectReason, RejectWriter, REJECT};
+use crate::pipeline::validation::{check_records_number, check_sequence};
+use crate::registry::vehicle::VehicleRegistry;
+use crate::repository::job::{BatchStatus, JobExecution, JobRepository, StepExecution};
+use crate::routing::table::RoutingStrategy;
+use crate::routing::vin;
+use crate::sftp_client::client;
+use crate::sql_client::client::OracleClient;
+use crate::sql_client::sequence;
+
+/* CONSTANTS */
+static SOURCE: &str = "source";
+static TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S%3f";
+
+// Outcome of one pass of the work loop on a flow
+#[derive(Debug, PartialEq)]
+pub enum RunStatus {
+    Worked,
+    Idle,
+    // The source is left in place after a database failure, the flow waits for the next poll
+    Interrupted,
+}
+
+// A rejected or undelivered source is done with, an interrupted one is processed again by the next poll
+enum RunError {
+    Failed(String),
+    Interrupted(String),
+}
+
+pub struct Pipeline<'a> {
+    general: &'a GeneralConfig,
+    flow: &'a FlowConfig,
+    vin_cache: &'a RefCell<VinCache>,
+    dry_run: bool,
+}
+
+struct FlowPaths {
+    archive_source: PathBuf,
+    failure_source: PathBuf,
+    rejected_source: PathBuf,
+    workspace_reject: PathBuf,
+    archive_reject: PathBuf,
+    state: PathBuf,
+    destinations: Vec<DestinationPaths>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(general: &'a GeneralConfig, flow: &'a FlowConfig, vin_cache: &'a RefCell<VinCache>, dry_run: bool) -> Pipeline<'a> {
+        Pipeline {general, flow, vin_cache, dry_run}
+    }
+
+    pub fn name(&self) -> String {
+        self.flow.name()
+    }
+
+    fn dirs(&self, area: &'a str, kind: &'a str) -> Vec<&'a str> {
+        vec![self.flow.root(self.general), &self.flow.system, &self.flow.flow, area, kind]
+    }
+
+    // Runs one iteration of the work loop as a job execution
+    pub fn run_once(&self, client_dbaindi: &OracleClient, client_dbaobt: &OracleClient, registry: &dyn VehicleRegistry, jobs: &dyn JobRepository) -> RunStatus {
+        let mut job = JobExecution::new(&self.flow.batch_name, &self.flow.name());
+        match self.run_steps(client_dbaindi, client_dbaobt, registry, jobs, &mut job) {
+            Ok(true) => {
+                job.end(jobs, BatchStatus::Completed, "COMPLETED", "");
+                RunStatus::Worked
+            },
+            Ok(false) => {
+                job.end(jobs, BatchStatus::Completed, "NOOP", "There are no available sources");
+                RunStatus::Idle
+            },
+            Err(RunError::Failed(e)) => {
+                job.end(jobs, BatchStatus::Failed, "FAILED", &e);
+                RunStatus::Worked
+            },
+            Err(RunError::Interrupted(e)) => {
+                job.end(jobs, BatchStatus::Failed, "INTERRUPTED", &e);
+                RunStatus::Interrupted
+            },
+        }
+    }
+
+    fn run_steps(&self, client_dbaindi: &OracleClient, client_dbaobt: &OracleClient, registry: &dyn VehicleRegistry, jobs: &dyn JobRepository, job: &mut JobExecution) -> Result<bool, RunError> {
+        // 1. Initialize File system (paths creation, the workspace is cleaned before each split)
+        let paths = self.init_paths();
+        // 2. Download all source files from sftp server
+        // The job and this step are recorded once a source or a queued delivery is found
+        let mut download = job.step(jobs, "download");
+        if self.dry_run {
+            println!("Dry run: skipped download of remote sources");
+        } else {
+            self.download(&paths, &mut download);
+        }
+        // 3. Select source file: a source interrupted after its split is resumed first, otherwise the oldest one
+        let mut checkpoint = match Checkpoint::pending(&paths.state) {
+            Some(checkpoint) if self.dry_run => {
+                println!("Dry run: source {} was interrupted, a normal run must resume it first", checkpoint.source);
+                download.end(jobs, BatchStatus::Completed, "");
+                return Ok(false);
+            },
+            Some(checkpoint) => {
+                println!("Resuming source file {} after step {:?}", checkpoint.source, checkpoint.state);
+                record_job(jobs, job, download);
+                checkpoint
+            },
+            None => {
+                let sources = fs::read_dir(&paths.failure_source).unwrap();
+                let mut source_files: Vec<PathBuf> = sources.map(|f| {f.unwrap().path()}).collect();
+                source_files.sort();
+                println!("Source files: {:?}", source_files);
+                // Reservations are kept by remote name, a source waits until the earlier one with its name is delivered
+                let undelivered: Vec<String> = Checkpoint::all(&paths.state).into_iter()
+                    .filter(|c| c.state == FileState::Queued)
+                    .filter_map(|c| c.source.split_once('_').map(|(_, name)| name.to_string()))
+                    .collect();
+                source_files.retain(|f| match f.file_name().and_then(|n| n.to_str()).and_then(|n| n.split_once('_')) {
+                    Some((_, name)) if undelivered.iter().any(|u| u == name) => {
+                        println!("Held back source file {:?}, the output of an earlier {} is not delivered yet", f, name);
+                        false
+                    },
+                    _ => true,
+                });
+                match source_files.first() {
+                    Some(f) => {
+                        record_job(jobs, job, download);
+                        Checkpoint::new(f.file_name().unwrap().to_str().unwrap())
+                    },
+                    None => {
+                        println!("There are no available sources");
+                        // Outputs queued before a restart are delivered even without new sources
+                        if !self.dry_run && Checkpoint::all(&paths.state).iter().any(|c| c.state == FileState::Queued) {
+                            record_job(jobs, job, download);
+                            if let Err(e) = self.deliver(&paths, client_dbaindi, client_dbaobt, jobs, job) {
+                                println!("Delivery failed, queued files are left for the next run: {}", e);
+                            }
+                            self.complete_deliveries(&paths);
+                        } else {
+                            download.end(jobs, BatchStatus::Completed, "");
+                        }
+                        return Ok(false);
+                    }
+                }
+            }
+        };
+        let f = paths.failure_source.join(&checkpoint.source);
+        println!("Working on source file: {:?}", f);
+        let source_filename = checkpoint.source.split_once("_").unwrap().1.to_string();
+        job.source = Some(source_filename.clone());
+        if checkpoint.state == FileState::Downloaded {
+            // 4. Reserve destination sequences and split lines based on movement code, in a clean workspace
+            self.clean_workspace();
+            let mut step = job.step(jobs, "split");
+            // Database errors leave the source in place for the next poll, reservations are resumed then
+            match self.check_sequences(client_dbaindi, &source_filename) {
+                Ok(None) => (),
+                Ok(Some(reason)) => {
+                    self.reject_source(&f, &paths, &reason);
+                    step.end(jobs, BatchStatus::Failed, &reason);
+                    return Err(RunError::Failed(format!("source rejected: {}", reason)));
+                },
+                Err(e) => {
+                    step.end(jobs, BatchStatus::Failed, &e);
+                    return Err(RunError::Interrupted(e));
+                },
+            }
+            let sequences = match self.reserve_sequences(client_dbaindi, &source_filename) {
+                Ok(sequences) => sequences,
+                Err(e) => {
+                    step.end(jobs, BatchStatus::Failed, &e);
+                    return Err(RunError::Interrupted(e));
+                }
+            };
+            let (outputs, reject) = match self.split(&f, &paths, &sequences, registry, &mut step) {
+                Ok(split) => split,
+                Err(reason) => {
+                    if let Err(e) = self.release_sequences(client_dbaindi, &sequences, &source_filename) {
+                        step.end(jobs, BatchStatus::Failed, &e);
+                        return Err(RunError::Interrupted(e));
+                    }
+                    // Partial output files stay in the workspace until the next cleanup
+                    self.reject_source(&f, &paths, &reason);
+                    step.end(jobs, BatchStatus::Failed, &reason);
+                    return Err(RunError::Failed(format!("source rejected: {}", reason)));
+                }
+            };
+            step.end(jobs, BatchStatus::Completed, "");
+            if self.dry_run {
+                for (destination, (path, lines)) in self.flow.destinations.iter().zip(&outputs) {
+                    println!("Dry run: {} {} records, output file left in {:?}", lines, destination.name, path);
+                }
+                println!("Dry run: {} rejected records, reject file left in {:?}", reject.1, reject.0);
+                return Ok(true);
+            }
+            checkpoint.set_split(&sequences, &outputs, &reject);
+            checkpoint.save(&paths.state, FileState::Split);
+        }
+        // Steps below may be resumed after a crash, so they skip the files already moved
+        let mut step = job.step(jobs, "archive");
+        if checkpoint.state == FileState::Split {
+            // Delete output files without body records, their sequence values go to the next source
+            for ((destination, (path, lines)), reservation) in self.flow.destinations.iter().zip(checkpoint.split_files()).zip(&checkpoint.sequences()) {
+                if lines == 0 {
+                    if path.exists() {
+                        fs::remove_file(&path).unwrap();
+                        println!("Deleted empty {} file: {:?}", destination.name, path);
+                    }
+                    // The checkpoint stays split, so that the release is retried on resume
+                    if let Err(e) = self.release_sequences(client_dbaindi, std::slice::from_ref(reservation), &source_filename) {
+                        step.end(jobs, BatchStatus::Failed, &e);
+                        return Err(RunError::Interrupted(e));
+                    }
+                }
+            }
+            // 5. Archive source file
+            if f.exists() {
+                archive_file(f.to_owned(), paths.archive_source.to_owned(), TIMESTAMP_FORMAT);
+                step.read_count += 1;
+                step.write_count += 1;
+            }
+            // Archive rejected records next to the source
+            let reject_path = checkpoint.reject_path.clone().unwrap();
+            let mut renamed_file = paths.workspace_reject.to_owned();
+            renamed_file.push(&source_filename);
+            if reject_path.exists() {
+                if checkpoint.rejected == 0 {
+                    fs::remove_file(&reject_path).unwrap();
+                } else {
+                    rename(&reject_path, &renamed_file).unwrap();
+                }
+            }
+            if renamed_file.exists() {
+                let archived_file = archive_file(renamed_file, paths.archive_reject.to_owned(), TIMESTAMP_FORMAT);
+                println!("Archived {} rejected records: {:?}", checkpoint.rejected, archived_file);
+                step.write_count += 1;
+            }
+            checkpoint.save(&paths.state, FileState::Archived);
+        }
+        // Place output files in delivery queues
+        for (destination, destination_paths) in self.flow.destinations.iter().zip(&paths.destinations) {
+            let outputs = fs::read_dir(&destination_paths.workspace).unwrap();
+            let mut output_files: Vec<PathBuf> = outputs.map(|f| {f.unwrap().path()}).collect();
+            output_files.sort();
+            println!("Temp {} files: {:?}", destination.name, output_files);
+            for o in output_files {
+                let mut renamed_file = o.parent().unwrap().to_owned();
+                renamed_file.push(&source_filename);
+                rename(o, &renamed_file).unwrap();
+                let failure_file = archive_file(renamed_file, destination_paths.failure.to_owned(), TIMESTAMP_FORMAT);
+                println!("Moved under {} queue file: {:?}", destination.name, failure_file);
+                step.write_count += 1;
+            }
+        }
+        checkpoint.save(&paths.state, FileState::Queued);
+        step.end(jobs, BatchStatus::Completed, "");
+        // 6. Deliver output files
+        let delivered = self.deliver(&paths, client_dbaindi, client_dbaobt, jobs, job);
+        self.complete_deliveries(&paths);
+        if let Err(e) = delivered {
+            println!("Delivery failed, queued files are left for the next run: {}", e);
+            return Err(RunError::Failed(e));
+        }
+        Ok(true)
+    }
+
+    fn clean_workspace(&self) {
+        for d in &self.flow.destinations {
+            init_path(self.dirs(&self.general.workspace, &d.name), true);
+        }
+        init_path(self.dirs(&self.general.workspace, REJECT), true);
+    }
+
+    // Queued sources without files left in the delivery queues are delivered
+    fn complete_deliveries(&self, paths: &FlowPaths) {
+        let queued: Vec<String> = paths.destinations.iter()
+            .flat_map(|d| fs::read_dir(&d.failure).unwrap())
+            .filter_map(|f| f.unwrap().file_name().to_str().and_then(|n| n.split_once("_")).map(|(_, name)| name.to_string()))
+            .collect();
+        for checkpoint in Checkpoint::all(&paths.state) {
+            if checkpoint.state == FileState::Queued && !queued.iter().any(|name| checkpoint.source.split_once("_").map(|(_, source)| source) == Some(name.as_str())) {
+                checkpoint.deliver(&paths.state);
+            }
+        }
+    }
+
+    fn reject_source(&self, f: &Path, paths: &FlowPaths, reason: &str) {
+        if self.dry_run {
+            println!("Dry run: source file would be rejected: {}", reason);
+        } else {
+            reject_file(f.to_owned(), paths.rejected_source.to_owned(), reason);
+        }
+    }
+
+    // Reason why the source must be rejected when one of its sequences cannot be used, checked before
+    // reserving so that a source mapped to a missing sequence reserves nothing
+    fn check_sequences(&self, client_dbaindi: &OracleClient, source_filename: &str) -> Result<Option<String>, String> {
+        for d in &self.flow.destinations {
+            let name = sequence::sequence_name(&self.flow.system, source_filename, &d.name);
+            let missing = client_dbaindi.execute(|conn| sequence::missing_sequence(conn, &d.sequence_schema, &name)).map_err(|e| format!("cannot check sequence {}: {}", name, e))?;
+            if let Some(reason) = missing {
+                return Ok(Some(format!("{} destination: {}", d.name, reason)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Reserves the sequence value of each destination for the source, dry runs only preview the values a run would take
+    fn reserve_sequences(&self, client_dbaindi: &OracleClient, source_filename: &str) -> Result<Vec<(String, u64)>, String> {
+        self.flow.destinations.iter().map(|d| {
+            let name = sequence::sequence_name(&self.flow.system, source_filename, &d.name);
+            let value = if self.dry_run {
+                client_dbaindi.execute(|conn| sequence::preview(conn, &d.sequence_schema, &name, source_filename))
+            } else {
+                client_dbaindi.transaction(|conn| sequence::reserve(conn, &d.sequence_schema, &name, source_filename))
+            };
+            value.map(|v| (name.to_string(), v)).map_err(|e| format!("cannot reserve sequence {}: {}", name, e))
+        }).collect()
+    }
+
+    fn release_sequences(&self, client_dbaindi: &OracleClient, sequences: &[(String, u64)], source_filename: &str) -> Result<(), String> {
+        if self.dry_run {
+            return Ok(());
+        }
+        for (name, value) in sequences {
+            client_dbaindi.transaction(|conn| sequence::release(conn, name, *value, source_filename)).map_err(|e| format!("cannot release sequence {} value {}: {}", name, value, e))?;
+        }
+        Ok(())
+    }
+
+    fn confirm_sequence(&self, client_dbaindi: &OracleClient, destination: &DestinationConfig, filename: &str) -> Result<(), String> {
+        let name = sequence::sequence_name(&self.flow.system, filename, &destination.name);
+        let confirmed = client_dbaindi.transaction(|conn| sequence::confirm(conn, &name, filename)).map_err(|e| format!("cannot confirm sequence {} for {}: {}", name, filename, e))?;
+        if !confirmed {
+            println!("No {} reservation to confirm for {}", name, filename);
+        }
+        Ok(())
+    }
+
+    pub fn replay(&self, archived: &Path, client_dbaindi: &OracleClient) -> Result<PathBuf, String> {
+        let archive_source: PathBuf = self.dirs(&self.general.archive, SOURCE).iter().collect();
+        let archived = if archived.is_absolute() || archived.exists() {
+            archived.to_owned()
+        } else {
+            archive_source.join(archived)
+        };
+        match (archived.parent().and_then(|p| p.canonicalize().ok()), archive_source.canonicalize().ok()) {
+            (Some(parent), Some(archive)) if parent == archive => {},
+            _ => return Err(format!("{:?} is not an archived source of flow {}", archived, self.name())),
+        }
+        if !archived.is_file() {
+            return Err(format!("archived source {:?} does not exist", archived));
+        }
+        // Archived sources are named <archive timestamp>_<download timestamp>_<remote name>
+        let queued_name = match archived.file_name().and_then(|n| n.to_str()).and_then(|n| n.split_once("_")) {
+            Some((_, name)) if name.contains('_') => name.to_string(),
+            _ => return Err(format!("{:?} is not an archived source file name", archived)),
+        };
+        let failure_source = init_path(self.dirs(&self.general.failure, SOURCE), false);
+        let mut queued = failure_source;
+        queued.push(&queued_name);
+        if queued.exists() {
+            return Err(format!("{:?} is already queued", queued));
+        }
+        // Reservations are kept by remote name, a replay would get the sequence values of the undelivered run
+        let remote_name = queued_name.split_once("_").unwrap().1;
+        for d in &self.flow.destinations {
+            let name = sequence::sequence_name(&self.flow.system, remote_name, &d.name);
+            let reserved = client_dbaindi.execute(|conn| sequence::is_reserved(conn, &name, remote_name)).map_err(|e| format!("cannot check sequence {}: {}", name, e))?;
+            if reserved {
+                return Err(format!("{} still has a {} reservation, its {} output is not delivered yet", remote_name, name, d.name));
+            }
+        }
+        fs::copy(&archived, &queued).map_err(|e| format!("cannot queue {:?}: {}", archived, e))?;
+        println!("Queued archived source for replay: {:?} -> {:?}", archived, queued);
+        Ok(queued)
+    }
+
+    pub fn status(&self) {
+        println!("Flow {}", self.name());
+        let mut queues = vec![(&self.general.failure, SOURCE), (&self.general.rejected, SOURCE)];
+        queues.extend(self.flow.destinations.iter().map(|d| (&self.general.failure, d.name.as_str())));
+        queues.extend(self.flow.destinations.iter().map(|d| (&self.general.workspace, d.name.as_str())));
+        queues.push((&self.general.state, SOURCE));
+        for (area, kind) in queues {
+            let path: PathBuf = self.dirs(area, kind).iter().collect();
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(_) => {
+                    println!("  {:?}: missing", path);
+                    continue;
+                }
+            };
+            let mut files: Vec<(String, u64)> = entries.filter_map(|e| e.ok())
+                .map(|e| (e.file_name().to_string_lossy().to_string(), e.metadata().map(|m| m.len()).unwrap_or(0)))
+                .collect();
+            files.sort();
+            println!("  {:?}: {} files", path, files.len());
+            for (name, size) in files {
+                println!("    {} ({} bytes)", name, size);
+            }
+        }
+    }
+
+    fn init_paths(&self) -> FlowPaths {
+        let general = self.general;
+        let destinations = self.flow.destinations.iter().map(|d| DestinationPaths {
+            workspace: init_path(self.dirs(&general.workspace, &d.name), false),
+            archive: init_path(self.dirs(&general.archive, &d.name), false),
+            failure: init_path(self.dirs(&general.failure, &d.name), false),
+        }).collect();
+        FlowPaths {
+            archive_source: init_path(self.dirs(&general.archive, SOURCE), false),
+            failure_source: init_path(self.dirs(&general.failure, SOURCE), false),
+            rejected_source: init_path(self.dirs(&general.rejected, SOURCE), false),
+            workspace_reject: init_path(self.dirs(&general.workspace, REJECT), false),
+            state: init_path(self.dirs(&general.state, SOURCE), false),
+            archive_reject: init_path(self.dirs(&general.archive, REJECT), false),
+            destinations,
+        }
+    }
+
+    fn download(&self, paths: &FlowPaths, step: &mut StepExecution) {
+        let source_sftp = &self.flow.source.sftp;
+        let mut client_sftp = client::sftp_connect(source_sftp.host.to_owned(), source_sftp.port, source_sftp.username.to_owned(), source_sftp.password.to_owned());
+        let sources = sftp_find_sources(&mut client_sftp, source_sftp.path.to_owned(), self.flow.source.file.to_owned(), &self.flow.source);
+        step.read_count = sources.len() as u64;
+        for source in sources {
+            let now: DateTime<Utc> = SystemTime::now().into();
+            client::sftp_get(&mut client_sftp, &source, &paths.failure_source, now.format(TIMESTAMP_FORMAT).to_string(), self.flow.line_terminator);
+            step.write_count += 1;
+            if self.flow.source.sftp_delete_remote {
+                client::sftp_rm(&mut client_sftp, &source);
+            }
+        }
+    }
+
+    // First pass: distinct VINs of the body records routed by vin_lookup that pass the VIN check,
+    // lines that cannot be read are left to the routing pass
+    fn collect_vins(&self, f: &Path) -> Vec<String> {
+        let layout = &self.flow.layout;
+        let routing = &self.flow.routing;
+        let source_encoding = encoding::lookup(&self.flow.source.encoding).unwrap();
+        let mut seen = HashSet::new();
+        let mut vins = Vec::new();
+        let mut lines = source_lines(f).enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let bytes = line.unwrap();
+            let last = lines.peek().is_none();
+            if i == 0 && layout.header.is_some() {
+                continue;
+            }
+            let (mut content, _) = LineTerminator::split(&bytes);
+            if i == 0 && content.starts_with(UTF8_BOM) && source_encoding == UTF_8 {
+                content = &content[UTF8_BOM.len()..];
+            }
+            let line = match encoding::decode(source_encoding, content) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            if layout.footer.as_ref().filter(|footer| footer.is_footer(&line, last)).is_some() {
+                continue;
+            }
+            let record = match layout.body.parse(i + 1, &line) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            if *routing.strategy(record.get(MOVEMENT_CODE)) != RoutingStrategy::VinLookup {
+                continue;
+            }
+            let vin = record.get(VIN).unwrap_or_default().trim();
+            if vin::validate(vin, routing.vin_lookup.check).is_ok() && seen.insert(vin.to_string()) {
+                vins.push(vin.to_string());
+            }
+        }
+        vins
+    }
+
+    // Splits the source file into one workspace temp file per destination in a routing pass, once the VINs to look up are resolved,
+    // returns their paths and body lines, plus the reject file and its records, or the reason why the source must be rejected
+    fn split(&self, f: &Path, paths: &FlowPaths, sequences: &[(String, u64)], registry: &dyn VehicleRegistry, step: &mut StepExecution) -> Result<(Vec<SplitFile>, SplitFile), String> {
+        let vins = self.collect_vins(f);
+        let router = Router::new(&self.flow.routing, registry, &mut self.vin_cache.borrow_mut(), &vins);
+        let mut writers: Vec<DestinationWriter> = self.flow.destinations.iter().zip(&paths.destinations).zip(sequences).map(|((d, p), (_, value))| {
+            let sequence = format!("{:05}", value);
+            let output_encoding = encoding::lookup(self.flow.output_encoding(d)).unwrap();
+            DestinationWriter::create(d, &p.workspace, sequence, output_encoding, self.flow.line_terminator)
+        }).collect();
+        let source_encoding = encoding::lookup(&self.flow.source.encoding).unwrap();
+        let mut rejects = RejectWriter::create(&paths.workspace_reject, source_encoding, self.flow.line_terminator);
+        let mut body_lines: u64 = 0;
+        let mut dropped_lines: u64 = 0;
+        let mut header_found = false;
+        let mut footer_found = false;
+        let layout = &self.flow.layout;
+        let mut lines = source_lines(f).enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            // Reported line numbers are 1 based
+            let number = i + 1;
+            let bytes = line.unwrap();
+            let (mut content, terminator) = LineTerminator::split(&bytes);
+            // A UTF-8 BOM is not part of the header record, it is written back before the output headers
+            if i == 0 && content.starts_with(UTF8_BOM) && source_encoding == UTF_8 {
+                content = &content[UTF8_BOM.len()..];
+                for writer in writers.iter_mut() {
+                    writer.write_bom();
+                }
+            }
+            let line = encoding::decode(source_encoding, content).map_err(|e| format!("line {} is not valid: {}", number, e))?;
+            let last = lines.peek().is_none();
+            println!("Read line {:?}: {:?}", number, line);
+            if footer_found {
+                return Err(format!("record found after footer at line {}", number));
+            }
+            if let (Some(header), 0) = (&layout.header, i) { // Header
+                if !header.matches(&line) {
+                    return Err(format!("missing header, first line is {:?}", line));
+                }
+                if self.flow.validation.sequence_continuity {
+                    check_sequence(self.flow, &line, f, &paths.archive_source, source_encoding)?;
+                }
+                for writer in writers.iter_mut() {
+                    writer.write_header(header, number, &line, terminator)?;
+                }
+                header_found = true;
+            } else if let Some(footer) = layout.footer.as_ref().filter(|footer| footer.is_footer(&line, last)) { // Footer
+                if self.flow.validation.records_number {
+                    check_records_number(self.flow, number, &line, body_lines)?;
+                }
+                for writer in writers.iter_mut() {
+                    writer.write_footer(footer, number, &line, terminator)?;
+                }
+                footer_found = true;
+            } else { // Body
+                body_lines += 1;
+                // Records too short for the body layout are rejected instead of routed
+                let record = match layout.body.parse(number, &line) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        println!("Invalid line {:?}: {}", number, e);
+                        rejects.write(RejectReason::ShortLine, number, &line, terminator);
+                        continue;
+                    },
+                };
+                let destination = match router.route(number, record.get(MOVEMENT_CODE), record.get(VIN)) {
+                    Route::Destination(name) => name,
+                    Route::Drop => {
+                        dropped_lines += 1;
+                        continue;
+                    },
+                    Route::Reject(reason) => {
+                        rejects.write(reason, number, &line, terminator);
+                        continue;
+                    },
+                };
+                println!("{}", destination);
+                let index = self.flow.destination_index(destination).unwrap();
+                writers[index].write_record(&line, terminator).map_err(|e| format!("line {}: {}", number, e))?;
+            }
+        }
+        if layout.header.is_some() && !header_found {
+            return Err(String::from("missing header, source is empty"));
+        }
+        if layout.footer.is_some() && !footer_found {
+            return Err(format!("missing footer after {} body records", body_lines));
+        }
+        let outputs: Vec<SplitFile> = writers.into_iter().map(|w| w.finish()).collect();
+        let counts: Vec<String> = self.flow.destinations.iter().zip(&outputs).map(|(d, (_, lines))| format!("{} {}", d.name, lines)).collect();
+        println!("Split lines: body {}, {}, dropped {}, rejected {}", body_lines, counts.join(", "), dropped_lines, rejects.total());
+        if rejects.total() > 0 {
+            println!("Rejected records: {}", rejects.summary());
+        }
+        // Dropped records are read but neither written nor skipped
+        step.read_count = body_lines;
+        step.write_count = outputs.iter().map(|(_, lines)| lines).sum();
+        step.skip_count = rejects.total();
+        Ok((outputs, rejects.finish()))
+    }
+
+    // Stops at the first file that cannot be delivered, it stays queued with the following ones for the next run
+    // Each destination is an upload.<name> or insert.<name> step
+    fn deliver(&self, paths: &FlowPaths, client_dbaindi: &OracleClient, client_dbaobt: &OracleClient, jobs: &dyn JobRepository, job: &JobExecution) -> Result<(), String> {
+        for (destination, destination_paths) in self.flow.destinations.iter().zip(&paths.destinations) {
+            let queued = fs::read_dir(&destination_paths.failure).unwrap();
+            let mut queued_files: Vec<PathBuf> = queued.map(|f| {f.unwrap().path()}).collect();
+            queued_files.sort();
+            println!("Final {} files: {:?}", destination.name, queued_files);
+            if queued_files.is_empty() {
+                continue;
+            }
+            let phase = match &destination.delivery {
+                DeliveryConfig::Sftp(_) => "upload",
+                DeliveryConfig::Blob(_) => "insert",
+            };
+            let mut step = job.step(jobs, &format!("{}.{}", phase, destination.name));
+            step.read_count = queued_files.len() as u64;
+            match self.deliver_files(destination, destination_paths, queued_files, client_dbaindi, client_dbaobt, &mut step) {
+                Ok(()) => step.end(jobs, BatchStatus::Completed, ""),
+                Err(e) => {
+                    step.end(jobs, BatchStatus::Failed, &e);
+                    return Err(e);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn deliver_files(&self, destination: &DestinationConfig, destination_paths: &DestinationPaths, queued_files: Vec<PathBuf>, client_dbaindi: &OracleClient, client_dbaobt: &OracleClient, step: &mut StepExecution) -> Result<(), String> {
+        match &destination.delivery {
+            // Upload files on sftp server
+            DeliveryConfig::Sftp(sftp) => {
+                let mut client_sftp = client::sftp_connect(sftp.host.to_owned(), sftp.port, sftp.username.to_owned(), sftp.password.to_owned());
+                for f in queued_files {
+                    let mut remote_path = PathBuf::from(&sftp.path);
+                    let filename = f.file_name().unwrap().to_str().unwrap().split_once("_").unwrap().1.to_string();
+                    remote_path.push(&filename);
+                    client::sftp_put(&mut client_sftp, &f, &remote_path, self.flow.line_terminator);
+                    println!("Uploaded {} file: {:?} -> {:?}", destination.name, f, remote_path);
+                    self.confirm_sequence(client_dbaindi, destination, &filename)?;
+                    archive_file(f, destination_paths.archive.to_owned(), TIMESTAMP_FORMAT);
+                    step.write_count += 1;
+                }
+            },
+            // Insert files into database
+            DeliveryConfig::Blob(blob_config) => {
+                for f in queued_files {
+                    let filename = f.file_name().unwrap().to_str().unwrap().split_once("_").unwrap().1.to_string();
+                    let sequence_name = sequence::sequence_name(&self.flow.system, &filename, &destination.name);
+                    let sequence_value = client_dbaindi.execute(|conn| sequence::file_value(conn, &sequence_name, &filename)).map_err(|e| format!("cannot read sequence {} for {}: {}", sequence_name, filename, e))?;
+                    // Record insert, BLOB content and status update are committed together
+                    match client_dbaobt.transaction(|conn| db_insert(conn, &f, self.flow, destination, blob_config, sequence_value)) {
+                        Ok(true) => step.write_count += 1,
+                        Ok(false) => {
+                            println!("Skipped {} file already inserted: {:?}", destination.name, f);
+                            step.skip_count += 1;
+                        },
+                        Err(e) => return Err(format!("cannot insert {} file {:?}, rolled back: {}", destination.name, f, e)),
+                    }
+                    self.confirm_sequence(client_dbaindi, destination, &filename)?;
+                    remove_file(f).unwrap();
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+fn init_path(dirs: Vec<&str>, clean: bool) -> PathBuf {
+    let path: PathBuf = dirs.iter().collect();
+    if clean && path.exists() && path.is_dir() {
+        remove_dir_all(&path).unwrap();
+    }
+    match fs::create_dir_all(&path) {
+        Ok(()) => {
+            println!("Initialized path: {:?} (clean: {:?})", path, clean);
+            path
+        },
+        Err(e) => panic!("Cannot initialize path {:?}! {}", path, e),
+    }
+}
+
+fn sftp_find_sources(client: &mut SftpFs, path: String, filename: String, source_config: &SourceConfig) -> Vec<remotefs::File> {
+    let entries = client.list_dir(Path::new(&path)).unwrap();
+    let mut sources: Vec<remotefs::File> = Vec::new();
+    let re = Regex::new(&filename).unwrap();
+    let now = SystemTime::now();
+    for entry in entries {
+        if entry.is_file()
+            && (!source_config.sftp_check_lastmtime || (now.duration_since(entry.metadata.modified.unwrap_or(SystemTime::UNIX_EPOCH)).unwrap().as_secs() >= source_config.sftp_lastmtime))
+            && re.is_match(entry.name().as_str()) {
+                println!("Localized remote source file: {:?}", entry);
+                sources.push(entry);
+        }
+    }
+    sources
+}
+
+// Records the job execution with its download step, done before knowing whether there was work
+fn record_job(jobs: &dyn JobRepository, job: &mut JobExecution, mut download: StepExecution) {
+    job.record(jobs);
+    download.attach(jobs, job);
+    download.end(jobs, BatchStatus::Completed, "");
+}
+
+fn archive_file(file: PathBuf, new_path: PathBuf, format: &str) -> PathBuf {
+    let now: DateTime<Utc> = SystemTime::now().into();
+    let filename = now.format(format).to_string() + "_" + file.file_name().unwrap().to_str().unwrap();
+    let mut final_path = new_path.clone();
+    final_path.push(filename);
+    rename(&file, &final_path).unwrap();
+    println!("Archived file: {:?} -> {:?}", file, final_path);
+    final_path
+}
+
+fn reject_file(file: PathBuf, rejected_path: PathBuf, reason: &str) -> PathBuf {
+    let rejected_file = archive_file(file, rejected_path, TIMESTAMP_FORMAT);
+    let mut reason_file = rejected_file.clone().into_os_string();
+    reason_file.push(".reason");
+    fs::write(&reason_file, reason.to_string() + "\n").unwrap();
+    println!("Rejected file: {:?} ({})", rejected_file, reason);
+    rejected_file
+}
+
+// Lines keep their terminator, so that it can be preserved in the outputs
+fn source_lines(f: &Path) -> impl Iterator<Item = std::io::Result<Vec<u8>>> {
+    let mut br = BufReader::new(File::open(f).unwrap());
+    std::iter::from_fn(move || {
+        let mut buf = Vec::new();
+        match br.read_until(b'\n', &mut buf) {
+            Ok(0) => None,
+            res => Some(res.map(|_| buf)),
+        }
+    })
+}
+
+// Returns false when the same content was already inserted with the same name and sequence, other rows with the same content are reported
+fn db_insert(conn: &Connection, path: &PathBuf, flow: &FlowConfig, destination: &DestinationConfig, blob_config: &BlobConfig, sequence: Option<u64>) -> Result<bool, oracle::Error> {
+    let file = fs::File::open(path).map_err(io_error)?;
+    let metadata = file.metadata().map_err(io_error)?;
+    let name = path.file_name().unwrap().to_str().unwrap().split_once("_").unwrap().1.to_string();
+    let sha256 = blob::sha256(fs::File::open(path).map_err(io_error)?).map_err(io_error)?;
+    let duplicates_sql = "SELECT ID, FILE_NAME, FILE_SEQUENCE FROM OBT_FILE_BLOB WHERE FILE_SHA256 = :sha256 AND FLOW_NAME = :flow ORDER BY ID";
+    for row in conn.query_as_named::<(i64, String, Option<u64>)>(duplicates_sql, &[("sha256", &sha256), ("flow", &flow.batch_name)])? {
+        let (id, other_name, other_sequence) = row?;
+        if other_name == name && other_sequence == sequence {
+            println!("File {} (sequence {:?}) already inserted with ID {}", name, sequence, id);
+            return Ok(false);
+        }
+        println!("Duplicate content: {} has the SHA-256 of {} (sequence {:?}) inserted with ID {}", name, other_name, other_sequence, id);
+    }
+    let length = metadata.len();
+    let tmp: DateTime<Utc> = metadata.created().unwrap_or(metadata.created().unwrap_or(SystemTime::now())).into();
+    let creation = Timestamp::new(tmp.year(), tmp.month(), tmp.day(), tmp.hour(), tmp.minute(), tmp.second(), tmp.nanosecond());
+    let tmp: DateTime<Utc> = metadata.modified().unwrap_or(SystemTime::now()).into();
+    let update = Timestamp::new(tmp.year(), tmp.month(), tmp.day(), tmp.hour(), tmp.minute(), tmp.second(), tmp.nanosecond());
+    let nextval_sql = "SELECT OBT_FILE_BLOB_SEQ.NEXTVAL FROM DUAL";
+    let id = conn.query_row_as::<i64>(nextval_sql, &[])?;
+    println!("Got BLOB_SEQ next val: {:?}", id);
+    let insert_sql = "INSERT INTO OBT_FILE_BLOB (ID, FILE_NAME, FILE_LENGTH, FILE_CREATION, FILE_UPDATE, FILE_ENCODING, FILE_COMPRESSION, FILE_SHA256, FILE_SEQUENCE, FLOW_NAME) VALUES (:id, :name, :length, :creation, :updation, :encoding, :compression, :sha256, :sequence, :flow)";
+    let mut stmt = conn.statement(insert_sql).build()?;
+    stmt.execute_named(&[("id", &id), ("name", &name), ("length", &length), ("creation", &creation), ("updation", &update), ("encoding", &flow.output_encoding(destination)), ("compression", &blob_config.compression.column()), ("sha256", &sha256), ("sequence", &sequence), ("flow", &flow.batch_name)])?;
+    println!("Inserted BLOB record");
+    // Rows are counted while the file is streamed into the BLOB
+    let lines = db_insert_blob(conn, id, &file, length, blob_config)?;
+    let update_sql = "UPDATE OBT_FILE_BLOB SET STATUS = 100, FILE_TOTAL_ROWS = :file_total_rows WHERE ID = :id";
+    let mut stmt = conn.statement(update_sql).build()?;
+    stmt.execute_named(&[("id", &id), ("file_total_rows", &lines)])?;
+    println!("Updated status");
+    Ok(true)
+}
+
+fn db_insert_blob(conn: &Connection, id: i64, file: &fs::File, length: u64, blob_config: &BlobConfig) -> Result<u64, oracle::Error> {
+    let sql = "SELECT FILE_BLOB FROM OBT_FILE_BLOB WHERE ID = :id FOR UPDATE";
+    let mut stmt = conn.statement(sql).lob_locator().build()?;
+    let mut blob = stmt.query_row_as_named::<Blob>(&[("id", &id)])?;
+    blob.open_resource()?;
+    let content = blob::write(&mut blob, file, blob_config)?;
+    if content.length != length {
+        return Err(oracle::Error::InternalError(format!("read {} bytes, FILE_LENGTH is {}", content.length, length)));
+    }
+    let size = blob.size()?;
+    if size != content.stored {
+        return Err(oracle::Error::InternalError(format!("BLOB holds {} bytes, {} were written", size, content.stored)));
+    }
+    blob.close_resource()?;
+    println!("Inserted {} BLOB bytes ({} file bytes)", content.stored, content.length);
+    Ok(content.rows)
+}
@@ -0,0 +1,82 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::pipeline::cache::VinCache;
+use crate::registry::vehicle::VehicleRegistry;
+
+// Result of the registry lookup for the VINs of a source, VINs of a failed chunk keep the lookup error
+pub struct VinResolution {
+    found: HashSet<String>,
+    failed: HashMap<String, String>,
+}
+
+impl VinResolution {
+    pub fn exists(&self, vin: &str) -> Result<bool, String> {
+        match self.failed.get(vin) {
+            Some(e) => Err(e.to_string()),
+            None => Ok(self.found.contains(vin)),
+        }
+    }
+}
+
+// Looks up the distinct VINs missing from the cache with one registry request per chunk
+pub fn resolve_vins(registry: &dyn VehicleRegistry, cache: &mut VinCache, vins: &[String], chunk_size: usize) -> VinResolution {
+    if cache.needs_preload() {
+        preload_vins(registry, cache);
+    }
+    let mut resolution = VinResolution {found: HashSet::new(), failed: HashMap::new()};
+    let mut missing = Vec::new();
+    for vin in vins {
+        match cache.get(vin) {
+            Some(true) => {
+                resolution.found.insert(vin.to_string());
+            },
+            Some(false) => (),
+            None => missing.push(vin.to_string()),
+        }
+    }
+    let (hits, misses) = cache.take_counts();
+    println!("VIN cache: {} hits, {} misses", hits, misses);
+    let mut queries = 0;
+    for chunk in missing.chunks(chunk_size) {
+        queries += 1;
+        match registry.existing(chunk) {
+            Ok(found) => {
+                for vin in chunk {
+                    cache.insert(vin, found.contains(vin));
+                }
+                resolution.found.extend(found);
+            },
+            Err(e) => {
+                println!("VIN lookup failed for {} VINs: {}", chunk.len(), e);
+                resolution.failed.extend(chunk.iter().map(|v| (v.to_string(), e.to_string())));
+            },
+        }
+    }
+    println!("Resolved {} VINs in {} queries: {} found", vins.len(), queries, resolution.found.len());
+    resolution
+}
+
+// Keeps the whole registry in memory when it fits the cache capacity
+pub fn preload_vins(registry: &dyn VehicleRegistry, cache: &mut VinCache) {
+    let vins = match registry.count() {
+        Ok(count) if count as usize <= cache.capacity => match registry.all() {
+            Ok(vins) => {
+                println!("Preloaded {} VINs from {}", vins.len(), registry.name());
+                Some(vins)
+            },
+            Err(e) => {
+                println!("VIN preload failed: {}", e);
+                None
+            },
+        },
+        Ok(count) => {
+            println!("{} has {} VINs, more than the cache capacity {}: not preloaded", registry.name(), count, cache.capacity);
+            None
+        },
+        Err(e) => {
+            println!("VIN preload failed: {}", e);
+            None
+        },
+    };
+    cache.set_preloaded(vins);
+}
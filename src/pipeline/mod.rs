@@ -0,0 +1,9 @@
+pub mod blob;
+pub mod cache;
+pub mod checkpoint;
+pub mod destination;
+pub mod flow;
+pub mod lookup;
+pub mod reject;
+pub mod router;
+pub mod validation;
\ No newline at end of file
@@ -0,0 +1,73 @@
+use std::{collections::BTreeMap, fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use encoding_rs::Encoding;
+
+use crate::layout::encoding;
+use crate::layout::terminator::LineTerminator;
+use crate::pipeline::destination::{temp_path, SplitFile};
+
+pub static REJECT: &str = "reject";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RejectReason {
+    ShortLine,
+    InvalidVin,
+    UnknownMovement,
+    RejectedMovement,
+    LookupError,
+}
+
+impl RejectReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::ShortLine => "SHORT_LINE",
+            RejectReason::InvalidVin => "INVALID_VIN",
+            RejectReason::UnknownMovement => "UNKNOWN_MOVEMENT",
+            RejectReason::RejectedMovement => "REJECTED_MOVEMENT",
+            RejectReason::LookupError => "LOOKUP_ERROR",
+        }
+    }
+}
+
+// Bad records of a source, written as <reason code>;<line number>;<record>
+pub struct RejectWriter {
+    pub path: PathBuf,
+    counts: BTreeMap<RejectReason, u64>,
+    encoding: &'static Encoding,
+    terminator: LineTerminator,
+    writer: BufWriter<File>,
+}
+
+impl RejectWriter {
+    pub fn create(workspace: &Path, encoding: &'static Encoding, terminator: LineTerminator) -> RejectWriter {
+        let path = temp_path(workspace, REJECT);
+        println!("Temp {} file: {:?}", REJECT, path);
+        let writer = BufWriter::new(File::create(&path).unwrap());
+        RejectWriter {path, counts: BTreeMap::new(), encoding, terminator, writer}
+    }
+
+    pub fn write(&mut self, reason: RejectReason, number: usize, line: &str, terminator: &[u8]) {
+        println!("Rejected line {:?}: {}", number, reason.code());
+        let record = format!("{};{};{}", reason.code(), number, line);
+        // Records come from the source, so they are encodable back unless the source is not
+        let bytes = encoding::encode(self.encoding, &record).unwrap_or_else(|_| record.into_bytes());
+        self.writer.write_all(&bytes).unwrap();
+        let terminator: &[u8] = if terminator.is_empty() { b"\n" } else { terminator };
+        self.writer.write_all(self.terminator.apply(terminator)).unwrap();
+        *self.counts.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn summary(&self) -> String {
+        let counts: Vec<String> = self.counts.iter().map(|(r, n)| format!("{} {}", r.code(), n)).collect();
+        counts.join(", ")
+    }
+
+    pub fn finish(mut self) -> SplitFile {
+        self.writer.flush().unwrap();
+        let total = self.total();
+        (self.path, total)
+    }
+}
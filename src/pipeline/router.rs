@@ -0,0 +1,197 @@
+use crate::pipeline::cache::VinCache;
+use crate::pipeline::lookup::{resolve_vins, VinResolution};
+use crate::pipeline::reject::RejectReason;
+use crate::registry::vehicle::VehicleRegistry;
+use crate::routing::table::{RoutingConfig, RoutingStrategy};
+use crate::routing::vin;
+
+#[derive(Debug, PartialEq)]
+pub enum Route<'a> {
+    Destination(&'a str),
+    Drop,
+    Reject(RejectReason),
+}
+
+// Routes body records by movement code, the VINs of a source are resolved against the registry beforehand
+pub struct Router<'a> {
+    routing: &'a RoutingConfig,
+    resolution: VinResolution,
+}
+
+impl<'a> Router<'a> {
+    pub fn new(routing: &'a RoutingConfig, registry: &dyn VehicleRegistry, cache: &mut VinCache, vins: &[String]) -> Router<'a> {
+        let resolution = resolve_vins(registry, cache, vins, routing.vin_lookup.chunk_size);
+        Router {routing, resolution}
+    }
+
+    pub fn route(&self, number: usize, movement_code: Option<&str>, vin: Option<&str>) -> Route<'a> {
+        let routing = self.routing;
+        match routing.strategy(movement_code) {
+            RoutingStrategy::Destination(name) => Route::Destination(name),
+            RoutingStrategy::VinLookup => {
+                let vin = vin.unwrap_or_default().trim();
+                match vin::validate(vin, routing.vin_lookup.check) {
+                    Err(e) => {
+                        println!("Invalid VIN on line {:?}: {}", number, e);
+                        match &routing.vin_lookup.invalid {
+                            RoutingStrategy::Destination(name) => Route::Destination(name),
+                            RoutingStrategy::Drop => Route::Drop,
+                            _ => Route::Reject(RejectReason::InvalidVin),
+                        }
+                    },
+                    Ok(()) => match self.resolution.exists(vin) {
+                        Ok(true) => Route::Destination(&routing.vin_lookup.found),
+                        Ok(false) => Route::Destination(&routing.vin_lookup.missing),
+                        Err(e) => {
+                            println!("VIN lookup failed on line {:?}: {}", number, e);
+                            Route::Reject(RejectReason::LookupError)
+                        },
+                    },
+                }
+            },
+            RoutingStrategy::Drop => {
+                println!("drop movement {:?}", movement_code);
+                Route::Drop
+            },
+            RoutingStrategy::Reject => {
+                if routing.is_mapped(movement_code) {
+                    Route::Reject(RejectReason::RejectedMovement)
+                } else {
+                    Route::Reject(RejectReason::UnknownMovement)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashSet};
+
+    use super::*;
+    use crate::pipeline::cache::VinCacheConfig;
+    use crate::registry::vehicle::MemoryRegistry;
+
+    static FOUND: &str = "1M8GDM9AXKP042788";
+    static MISSING: &str = "1HGCM82633A004352";
+
+    fn routing(extra: &str) -> RoutingConfig {
+        let config = format!("default = \"reject\"\n[movements]\n\"10\" = \"vin_lookup\"\n\"20\" = \"drop\"\n\"30\" = \"reject\"\n\"40\" = \"legacy\"\n[vin_lookup]\nfound = \"obt\"\nmissing = \"legacy\"\n{}", extra);
+        toml::from_str(&config).unwrap()
+    }
+
+    fn no_cache() -> VinCache {
+        VinCache::new(&VinCacheConfig {capacity: 0, ttl: 3600, preload: false})
+    }
+
+    fn vins(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    struct FailingRegistry;
+
+    impl VehicleRegistry for FailingRegistry {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn existing(&self, _: &[String]) -> Result<HashSet<String>, String> {
+            Err(String::from("ORA-03113: end-of-file on communication channel"))
+        }
+
+        fn count(&self) -> Result<u64, String> {
+            Err(String::from("unavailable"))
+        }
+
+        fn all(&self) -> Result<HashSet<String>, String> {
+            Err(String::from("unavailable"))
+        }
+    }
+
+    // Counts the registry queries
+    struct CountingRegistry {
+        registry: MemoryRegistry,
+        queries: Cell<usize>,
+    }
+
+    impl VehicleRegistry for CountingRegistry {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn existing(&self, vins: &[String]) -> Result<HashSet<String>, String> {
+            self.queries.set(self.queries.get() + 1);
+            self.registry.existing(vins)
+        }
+
+        fn count(&self) -> Result<u64, String> {
+            self.registry.count()
+        }
+
+        fn all(&self) -> Result<HashSet<String>, String> {
+            self.registry.all()
+        }
+    }
+
+    #[test]
+    fn routes_found_and_missing_vins() {
+        let routing = routing("");
+        let registry = MemoryRegistry::new(&vins(&[FOUND]));
+        let router = Router::new(&routing, &registry, &mut no_cache(), &vins(&[FOUND, MISSING]));
+        assert_eq!(router.route(1, Some("10"), Some(FOUND)), Route::Destination("obt"));
+        assert_eq!(router.route(2, Some("10"), Some(MISSING)), Route::Destination("legacy"));
+        assert_eq!(router.route(3, Some("10 "), Some(" 1M8GDM9AXKP042788 ")), Route::Destination("obt"));
+    }
+
+    #[test]
+    fn routes_invalid_vins() {
+        let registry = MemoryRegistry::new(&vins(&[FOUND]));
+        let rejecting = routing("");
+        let router = Router::new(&rejecting, &registry, &mut no_cache(), &[]);
+        assert_eq!(router.route(1, Some("10"), Some("1M8GDM9AXKP04278")), Route::Reject(RejectReason::InvalidVin));
+        assert_eq!(router.route(2, Some("10"), Some("   ")), Route::Reject(RejectReason::InvalidVin));
+        assert_eq!(router.route(3, Some("10"), None), Route::Reject(RejectReason::InvalidVin));
+        let dropping = routing("invalid = \"drop\"");
+        let router = Router::new(&dropping, &registry, &mut no_cache(), &[]);
+        assert_eq!(router.route(1, Some("10"), Some("1M8GDM9AXKP0427O8")), Route::Drop);
+        let redirecting = routing("invalid = \"legacy\"");
+        let router = Router::new(&redirecting, &registry, &mut no_cache(), &[]);
+        assert_eq!(router.route(1, Some("10"), Some("1M8GDM9AXKP0427O8")), Route::Destination("legacy"));
+    }
+
+    #[test]
+    fn rejects_records_when_the_lookup_fails() {
+        let routing = routing("");
+        let router = Router::new(&routing, &FailingRegistry, &mut no_cache(), &vins(&[FOUND]));
+        assert_eq!(router.route(1, Some("10"), Some(FOUND)), Route::Reject(RejectReason::LookupError));
+        // Records that need no lookup are still routed
+        assert_eq!(router.route(2, Some("40"), Some(FOUND)), Route::Destination("legacy"));
+    }
+
+    #[test]
+    fn routes_by_movement_code() {
+        let routing = routing("");
+        let registry = MemoryRegistry::new(&[]);
+        let router = Router::new(&routing, &registry, &mut no_cache(), &[]);
+        assert_eq!(router.route(1, Some("20"), Some(FOUND)), Route::Drop);
+        assert_eq!(router.route(2, Some("30"), Some(FOUND)), Route::Reject(RejectReason::RejectedMovement));
+        assert_eq!(router.route(3, Some("99"), Some(FOUND)), Route::Reject(RejectReason::UnknownMovement));
+        assert_eq!(router.route(4, None, Some(FOUND)), Route::Reject(RejectReason::UnknownMovement));
+    }
+
+    #[test]
+    fn chunked_resolution_matches_line_lookups() {
+        let routing = routing("chunk_size = 2");
+        let known = vins(&[FOUND, "JH4KA7561PC008269", "WVWZZZ1JZXW000001"]);
+        let source = vins(&[FOUND, MISSING, "JH4KA7561PC008269", "WVWZZZ1JZXW000001", "SALLDHMF7AA000002"]);
+        let registry = CountingRegistry {registry: MemoryRegistry::new(&known), queries: Cell::new(0)};
+        let chunked = Router::new(&routing, &registry, &mut no_cache(), &source);
+        assert_eq!(registry.queries.get(), 3);
+        for (i, vin) in source.iter().enumerate() {
+            let line = Router::new(&routing, &registry, &mut no_cache(), &[vin.to_string()]);
+            assert_eq!(chunked.route(i, Some("10"), Some(vin)), line.route(i, Some("10"), Some(vin)));
+        }
+        assert_eq!(chunked.route(0, Some("10"), Some(FOUND)), Route::Destination("obt"));
+        assert_eq!(chunked.route(1, Some("10"), Some(MISSING)), Route::Destination("legacy"));
+    }
+}
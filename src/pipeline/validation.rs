@@ -0,0 +1,76 @@
+use std::{fs::{self, File}, io::{BufRead, BufReader}, path::Path};
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::config::settings::FlowConfig;
+use crate::layout::encoding;
+use crate::layout::record::{RECORDS_NUMBER, SEQUENCE};
+use crate::layout::terminator::UTF8_BOM;
+
+// Compares the footer records number with the body records read from the source
+pub fn check_records_number(flow: &FlowConfig, number: usize, footer_line: &str, body_lines: u64) -> Result<(), String> {
+    let footer = flow.layout.footer.as_ref().unwrap();
+    let record = footer.parse(number, footer_line).map_err(|e| format!("invalid footer: {}", e))?;
+    let declared = match record.number(RECORDS_NUMBER) {
+        Some(n) => n,
+        None => return Err(format!("footer records number is not numeric: {:?}", footer_line)),
+    };
+    if declared != body_lines {
+        return Err(format!("footer declares {} records, source contains {}", declared, body_lines));
+    }
+    Ok(())
+}
+
+// Checks that the header sequence follows the one of the last archived source with the same name prefix
+pub fn check_sequence(flow: &FlowConfig, header_line: &str, source: &Path, archive_source: &Path, source_encoding: &'static Encoding) -> Result<(), String> {
+    let header = flow.layout.header.as_ref().unwrap();
+    let record = header.parse(1, header_line).map_err(|e| format!("invalid header: {}", e))?;
+    let sequence = match record.number(SEQUENCE) {
+        Some(n) => n,
+        None => return Err(format!("header sequence is missing or not numeric: {:?}", header_line)),
+    };
+    // Queued sources are named <download timestamp>_<remote name>, archived ones get one more timestamp
+    let (remote, prefix) = match source.file_name().and_then(|n| n.to_str()).and_then(|n| n.split_once('_')) {
+        Some((_, name)) => (name.to_string(), remote_prefix(name).to_string()),
+        None => return Ok(()),
+    };
+    let mut archived: Vec<String> = fs::read_dir(archive_source).map_err(|e| format!("cannot read archive: {}", e))?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().map(|n| n.to_string()))
+        .filter(|n| n.splitn(3, '_').nth(2).map(remote_prefix) == Some(prefix.as_str()))
+        .collect();
+    archived.sort();
+    // A replayed source is already archived under its own name, it follows the sources archived before its first copy
+    if let Some(first) = archived.iter().position(|n| n.splitn(3, '_').nth(2) == Some(remote.as_str())) {
+        archived.truncate(first);
+    }
+    let previous = match archived.last() {
+        Some(name) => archive_source.join(name),
+        None => {
+            println!("No archived source for {}, sequence {} accepted", prefix, sequence);
+            return Ok(());
+        }
+    };
+    let mut previous_bytes = Vec::new();
+    BufReader::new(File::open(&previous).map_err(|e| format!("cannot open {:?}: {}", previous, e))?)
+        .read_until(b'\n', &mut previous_bytes)
+        .map_err(|e| format!("cannot read {:?}: {}", previous, e))?;
+    // Skipped as split does, the archived copy keeps the BOM in front of its header
+    let mut previous_content = &previous_bytes[..];
+    if previous_content.starts_with(UTF8_BOM) && source_encoding == UTF_8 {
+        previous_content = &previous_content[UTF8_BOM.len()..];
+    }
+    let previous_header = encoding::decode(source_encoding, previous_content).map_err(|e| format!("cannot read {:?}: {}", previous, e))?;
+    let previous_sequence = match header.parse(1, previous_header.trim_end_matches(&['\r', '\n'][..])).ok().and_then(|r| r.number(SEQUENCE)) {
+        Some(n) => n,
+        None => return Ok(()),
+    };
+    if sequence != previous_sequence + 1 {
+        return Err(format!("header sequence {} does not follow {} of {:?}", sequence, previous_sequence, previous));
+    }
+    Ok(())
+}
+
+// Name part shared by all the files of a source, e.g. FLOW for FLOW_20220101.txt
+fn remote_prefix(name: &str) -> &str {
+    name.rsplit_once('_').map(|(p, _)| p).unwrap_or(name)
+}
@@ -0,0 +1 @@
+pub mod vehicle;
\ No newline at end of file
@@ -0,0 +1,151 @@
+use std::{collections::HashSet, fs::File, io::{BufRead, BufReader}};
+use oracle::sql_type::ToSql;
+use serde::Deserialize;
+
+use crate::config::settings::lenient;
+use crate::sql_client::client::OracleClient;
+
+// Where the VINs known to OBT are looked up
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RegistryConfig {
+    Oracle,
+    File(FileRegistryConfig),
+    Memory {
+        #[serde(default)]
+        vins: Vec<String>,
+    },
+}
+
+// Snapshot with one VIN per line, or one column of a delimited file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileRegistryConfig {
+    pub path: String,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default, deserialize_with = "lenient")]
+    pub column: usize,
+    #[serde(default, deserialize_with = "lenient")]
+    pub header: bool,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+pub trait VehicleRegistry {
+    fn name(&self) -> &str;
+    // VINs of the chunk known to the registry
+    fn existing(&self, vins: &[String]) -> Result<HashSet<String>, String>;
+    fn count(&self) -> Result<u64, String>;
+    fn all(&self) -> Result<HashSet<String>, String>;
+}
+
+// OBT_VEHICLES table
+pub struct OracleRegistry<'a> {
+    client: &'a OracleClient,
+}
+
+impl<'a> OracleRegistry<'a> {
+    pub fn new(client: &'a OracleClient) -> OracleRegistry<'a> {
+        OracleRegistry {client}
+    }
+}
+
+impl<'a> VehicleRegistry for OracleRegistry<'a> {
+    fn name(&self) -> &str {
+        "OBT_VEHICLES"
+    }
+
+    fn existing(&self, vins: &[String]) -> Result<HashSet<String>, String> {
+        let binds: Vec<String> = (1..=vins.len()).map(|i| format!(":{}", i)).collect();
+        let select_sql = format!("SELECT DISTINCT VIN FROM OBT_VEHICLES WHERE VIN IN ({})", binds.join(", "));
+        let params: Vec<&dyn ToSql> = vins.iter().map(|v| v as &dyn ToSql).collect();
+        self.client.execute(|conn| conn.query_as::<String>(&select_sql, &params)?.collect()).map_err(|e| e.to_string())
+    }
+
+    fn count(&self) -> Result<u64, String> {
+        let count_sql = "SELECT COUNT(*) FROM OBT_VEHICLES";
+        self.client.execute(|conn| conn.query_row_as::<u64>(count_sql, &[])).map_err(|e| e.to_string())
+    }
+
+    fn all(&self) -> Result<HashSet<String>, String> {
+        let select_sql = "SELECT VIN FROM OBT_VEHICLES";
+        self.client.execute(|conn| conn.query_as::<String>(select_sql, &[])?.collect()).map_err(|e| e.to_string())
+    }
+}
+
+// Fixed VIN set from the configuration
+pub struct MemoryRegistry {
+    vins: HashSet<String>,
+}
+
+impl MemoryRegistry {
+    pub fn new(vins: &[String]) -> MemoryRegistry {
+        MemoryRegistry {vins: vins.iter().map(|v| v.trim().to_string()).collect()}
+    }
+}
+
+impl VehicleRegistry for MemoryRegistry {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn existing(&self, vins: &[String]) -> Result<HashSet<String>, String> {
+        Ok(vins.iter().filter(|v| self.vins.contains(*v)).cloned().collect())
+    }
+
+    fn count(&self) -> Result<u64, String> {
+        Ok(self.vins.len() as u64)
+    }
+
+    fn all(&self) -> Result<HashSet<String>, String> {
+        Ok(self.vins.clone())
+    }
+}
+
+// File snapshot of the registry, read once when opened
+pub struct FileRegistry {
+    path: String,
+    vins: HashSet<String>,
+}
+
+impl FileRegistry {
+    pub fn open(config: &FileRegistryConfig) -> Result<FileRegistry, String> {
+        let file = File::open(&config.path).map_err(|e| format!("cannot open {}: {}", config.path, e))?;
+        let mut vins = HashSet::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("cannot read {}: {}", config.path, e))?;
+            if i == 0 && config.header {
+                continue;
+            }
+            match line.split(config.delimiter).nth(config.column).map(|v| v.trim()) {
+                Some(vin) if !vin.is_empty() => {
+                    vins.insert(vin.to_string());
+                },
+                _ => (),
+            }
+        }
+        println!("Loaded {} VINs from {}", vins.len(), config.path);
+        Ok(FileRegistry {path: config.path.to_string(), vins})
+    }
+}
+
+impl VehicleRegistry for FileRegistry {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn existing(&self, vins: &[String]) -> Result<HashSet<String>, String> {
+        Ok(vins.iter().filter(|v| self.vins.contains(*v)).cloned().collect())
+    }
+
+    fn count(&self) -> Result<u64, String> {
+        Ok(self.vins.len() as u64)
+    }
+
+    fn all(&self) -> Result<HashSet<String>, String> {
+        Ok(self.vins.clone())
+    }
+}
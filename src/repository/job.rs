@@ -0,0 +1,292 @@
+use chrono::{DateTime, Utc};
+use rusqlite::params;
+use serde::Deserialize;
+
+use crate::sql_client::client::OracleClient;
+
+// Longest exit message stored, as in the Spring Batch schema; VARCHAR2(2500) counts bytes
+pub static MAX_EXIT_MESSAGE: usize = 2500;
+
+// Where job and step executions are recorded
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobRepositoryConfig {
+    None,
+    Oracle,
+    Sqlite {
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchStatus {
+    Started,
+    Completed,
+    Failed,
+}
+
+impl BatchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchStatus::Started => "STARTED",
+            BatchStatus::Completed => "COMPLETED",
+            BatchStatus::Failed => "FAILED",
+        }
+    }
+}
+
+// One pass of the work loop on a flow, the source is known once selected
+pub struct JobExecution {
+    pub id: Option<i64>,
+    pub job_name: String,
+    pub flow: String,
+    pub source: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub status: BatchStatus,
+    pub exit_code: String,
+    pub exit_message: String,
+}
+
+// One phase of a job execution: download, split, archive, upload or insert
+pub struct StepExecution {
+    pub id: Option<i64>,
+    pub job_id: Option<i64>,
+    pub name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub status: BatchStatus,
+    pub read_count: u64,
+    pub write_count: u64,
+    pub skip_count: u64,
+    pub exit_message: String,
+}
+
+pub trait JobRepository {
+    fn name(&self) -> &str;
+    // Stores a new execution and returns its id
+    fn insert_job(&self, job: &JobExecution) -> Result<i64, String>;
+    fn update_job(&self, job: &JobExecution) -> Result<(), String>;
+    fn insert_step(&self, step: &StepExecution) -> Result<i64, String>;
+    fn update_step(&self, step: &StepExecution) -> Result<(), String>;
+}
+
+// Repository failures are logged and never stop the work loop
+impl JobExecution {
+    // Recorded only once there is work to do, polls without sources are not stored
+    pub fn new(job_name: &str, flow: &str) -> JobExecution {
+        JobExecution {id: None, job_name: job_name.to_string(), flow: flow.to_string(), source: None, start_time: Utc::now(), end_time: None, status: BatchStatus::Started, exit_code: String::from(BatchStatus::Started.as_str()), exit_message: String::new()}
+    }
+
+    pub fn record(&mut self, repository: &dyn JobRepository) {
+        match repository.insert_job(self) {
+            Ok(id) => self.id = Some(id),
+            Err(e) => println!("Cannot record job execution in {} repository: {}", repository.name(), e),
+        }
+    }
+
+    pub fn step(&self, repository: &dyn JobRepository, name: &str) -> StepExecution {
+        let mut step = StepExecution {id: None, job_id: self.id, name: name.to_string(), start_time: Utc::now(), end_time: None, status: BatchStatus::Started, read_count: 0, write_count: 0, skip_count: 0, exit_message: String::new()};
+        if self.id.is_some() {
+            match repository.insert_step(&step) {
+                Ok(id) => step.id = Some(id),
+                Err(e) => println!("Cannot record {} step execution in {} repository: {}", name, repository.name(), e),
+            }
+        }
+        step
+    }
+
+    pub fn end(mut self, repository: &dyn JobRepository, status: BatchStatus, exit_code: &str, exit_message: &str) {
+        self.end_time = Some(Utc::now());
+        self.status = status;
+        self.exit_code = exit_code.to_string();
+        self.exit_message = truncate_message(exit_message);
+        println!("Job execution {:?} of {} ended: {} {}", self.id, self.flow, self.exit_code, self.exit_message);
+        if self.id.is_some() {
+            if let Err(e) = repository.update_job(&self) {
+                println!("Cannot record job execution in {} repository: {}", repository.name(), e);
+            }
+        }
+    }
+}
+
+impl StepExecution {
+    // Records a step started before its job execution was recorded
+    pub fn attach(&mut self, repository: &dyn JobRepository, job: &JobExecution) {
+        if self.id.is_some() || job.id.is_none() {
+            return;
+        }
+        self.job_id = job.id;
+        match repository.insert_step(self) {
+            Ok(id) => self.id = Some(id),
+            Err(e) => println!("Cannot record {} step execution in {} repository: {}", self.name, repository.name(), e),
+        }
+    }
+
+    pub fn end(mut self, repository: &dyn JobRepository, status: BatchStatus, exit_message: &str) {
+        self.end_time = Some(Utc::now());
+        self.status = status;
+        self.exit_message = truncate_message(exit_message);
+        println!("Step {} ended: {} (read {}, write {}, skip {}) {}", self.name, self.status.as_str(), self.read_count, self.write_count, self.skip_count, self.exit_message);
+        if self.id.is_some() {
+            if let Err(e) = repository.update_step(&self) {
+                println!("Cannot record {} step execution in {} repository: {}", self.name, repository.name(), e);
+            }
+        }
+    }
+}
+
+// Cut at MAX_EXIT_MESSAGE bytes on a character boundary
+fn truncate_message(message: &str) -> String {
+    let mut end = message.len().min(MAX_EXIT_MESSAGE);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message[..end].to_string()
+}
+
+// Nothing is recorded, also used by dry runs
+pub struct NoJobRepository;
+
+impl JobRepository for NoJobRepository {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn insert_job(&self, _job: &JobExecution) -> Result<i64, String> {
+        Ok(0)
+    }
+
+    fn update_job(&self, _job: &JobExecution) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn insert_step(&self, _step: &StepExecution) -> Result<i64, String> {
+        Ok(0)
+    }
+
+    fn update_step(&self, _step: &StepExecution) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// INDI_BATCH_JOB_EXECUTION and INDI_BATCH_STEP_EXECUTION tables of the INDI database (see sql/indi_batch_execution.sql)
+pub struct OracleJobRepository<'a> {
+    client: &'a OracleClient,
+}
+
+impl<'a> OracleJobRepository<'a> {
+    pub fn new(client: &'a OracleClient) -> OracleJobRepository<'a> {
+        OracleJobRepository {client}
+    }
+}
+
+impl<'a> JobRepository for OracleJobRepository<'a> {
+    fn name(&self) -> &str {
+        "INDI_BATCH_JOB_EXECUTION"
+    }
+
+    fn insert_job(&self, job: &JobExecution) -> Result<i64, String> {
+        let insert_sql = "INSERT INTO INDI_BATCH_JOB_EXECUTION (JOB_EXECUTION_ID, JOB_NAME, FLOW_NAME, START_TIME, STATUS, EXIT_CODE, LAST_UPDATED) VALUES (:id, :job_name, :flow, :start_time, :status, :exit_code, SYSTIMESTAMP)";
+        self.client.transaction(|conn| {
+            let id = conn.query_row_as::<i64>("SELECT INDI_BATCH_JOB_EXECUTION_SEQ.NEXTVAL FROM DUAL", &[])?;
+            conn.execute_named(insert_sql, &[("id", &id), ("job_name", &job.job_name), ("flow", &job.flow), ("start_time", &job.start_time), ("status", &job.status.as_str()), ("exit_code", &job.exit_code)])?;
+            Ok(id)
+        }).map_err(|e| e.to_string())
+    }
+
+    fn update_job(&self, job: &JobExecution) -> Result<(), String> {
+        let update_sql = "UPDATE INDI_BATCH_JOB_EXECUTION SET SOURCE_FILE = :source, END_TIME = :end_time, STATUS = :status, EXIT_CODE = :exit_code, EXIT_MESSAGE = :exit_message, LAST_UPDATED = SYSTIMESTAMP WHERE JOB_EXECUTION_ID = :id";
+        self.client.transaction(|conn| {
+            conn.execute_named(update_sql, &[("source", &job.source), ("end_time", &job.end_time), ("status", &job.status.as_str()), ("exit_code", &job.exit_code), ("exit_message", &job.exit_message), ("id", &job.id)])?;
+            Ok(())
+        }).map_err(|e| e.to_string())
+    }
+
+    fn insert_step(&self, step: &StepExecution) -> Result<i64, String> {
+        let insert_sql = "INSERT INTO INDI_BATCH_STEP_EXECUTION (STEP_EXECUTION_ID, JOB_EXECUTION_ID, STEP_NAME, START_TIME, STATUS, READ_COUNT, WRITE_COUNT, SKIP_COUNT, LAST_UPDATED) VALUES (:id, :job_id, :name, :start_time, :status, 0, 0, 0, SYSTIMESTAMP)";
+        self.client.transaction(|conn| {
+            let id = conn.query_row_as::<i64>("SELECT INDI_BATCH_STEP_EXECUTION_SEQ.NEXTVAL FROM DUAL", &[])?;
+            conn.execute_named(insert_sql, &[("id", &id), ("job_id", &step.job_id), ("name", &step.name), ("start_time", &step.start_time), ("status", &step.status.as_str())])?;
+            Ok(id)
+        }).map_err(|e| e.to_string())
+    }
+
+    fn update_step(&self, step: &StepExecution) -> Result<(), String> {
+        let update_sql = "UPDATE INDI_BATCH_STEP_EXECUTION SET END_TIME = :end_time, STATUS = :status, READ_COUNT = :read_count, WRITE_COUNT = :write_count, SKIP_COUNT = :skip_count, EXIT_MESSAGE = :exit_message, LAST_UPDATED = SYSTIMESTAMP WHERE STEP_EXECUTION_ID = :id";
+        self.client.transaction(|conn| {
+            conn.execute_named(update_sql, &[("end_time", &step.end_time), ("status", &step.status.as_str()), ("read_count", &step.read_count), ("write_count", &step.write_count), ("skip_count", &step.skip_count), ("exit_message", &step.exit_message), ("id", &step.id)])?;
+            Ok(())
+        }).map_err(|e| e.to_string())
+    }
+}
+
+// Local SQLite file with the same tables, created when missing
+pub struct SqliteJobRepository {
+    conn: rusqlite::Connection,
+}
+
+impl SqliteJobRepository {
+    pub fn open(path: &str) -> Result<SqliteJobRepository, String> {
+        let conn = rusqlite::Connection::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS INDI_BATCH_JOB_EXECUTION (
+                JOB_EXECUTION_ID INTEGER PRIMARY KEY AUTOINCREMENT,
+                JOB_NAME TEXT NOT NULL,
+                FLOW_NAME TEXT NOT NULL,
+                SOURCE_FILE TEXT,
+                START_TIME TEXT NOT NULL,
+                END_TIME TEXT,
+                STATUS TEXT NOT NULL,
+                EXIT_CODE TEXT NOT NULL,
+                EXIT_MESSAGE TEXT,
+                LAST_UPDATED TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS INDI_BATCH_STEP_EXECUTION (
+                STEP_EXECUTION_ID INTEGER PRIMARY KEY AUTOINCREMENT,
+                JOB_EXECUTION_ID INTEGER NOT NULL REFERENCES INDI_BATCH_JOB_EXECUTION (JOB_EXECUTION_ID),
+                STEP_NAME TEXT NOT NULL,
+                START_TIME TEXT NOT NULL,
+                END_TIME TEXT,
+                STATUS TEXT NOT NULL,
+                READ_COUNT INTEGER NOT NULL,
+                WRITE_COUNT INTEGER NOT NULL,
+                SKIP_COUNT INTEGER NOT NULL,
+                EXIT_MESSAGE TEXT,
+                LAST_UPDATED TEXT NOT NULL
+            );
+        ").map_err(|e| format!("{}: {}", path, e))?;
+        println!("Opened job repository: {}", path);
+        Ok(SqliteJobRepository {conn})
+    }
+}
+
+impl JobRepository for SqliteJobRepository {
+    fn name(&self) -> &str {
+        "SQLite"
+    }
+
+    fn insert_job(&self, job: &JobExecution) -> Result<i64, String> {
+        let insert_sql = "INSERT INTO INDI_BATCH_JOB_EXECUTION (JOB_NAME, FLOW_NAME, START_TIME, STATUS, EXIT_CODE, LAST_UPDATED) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        self.conn.execute(insert_sql, params![job.job_name, job.flow, job.start_time.to_rfc3339(), job.status.as_str(), job.exit_code, Utc::now().to_rfc3339()]).map_err(|e| e.to_string())?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn update_job(&self, job: &JobExecution) -> Result<(), String> {
+        let update_sql = "UPDATE INDI_BATCH_JOB_EXECUTION SET SOURCE_FILE = ?1, END_TIME = ?2, STATUS = ?3, EXIT_CODE = ?4, EXIT_MESSAGE = ?5, LAST_UPDATED = ?6 WHERE JOB_EXECUTION_ID = ?7";
+        self.conn.execute(update_sql, params![job.source, job.end_time.map(|t| t.to_rfc3339()), job.status.as_str(), job.exit_code, job.exit_message, Utc::now().to_rfc3339(), job.id]).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn insert_step(&self, step: &StepExecution) -> Result<i64, String> {
+        let insert_sql = "INSERT INTO INDI_BATCH_STEP_EXECUTION (JOB_EXECUTION_ID, STEP_NAME, START_TIME, STATUS, READ_COUNT, WRITE_COUNT, SKIP_COUNT, LAST_UPDATED) VALUES (?1, ?2, ?3, ?4, 0, 0, 0, ?5)";
+        self.conn.execute(insert_sql, params![step.job_id, step.name, step.start_time.to_rfc3339(), step.status.as_str(), Utc::now().to_rfc3339()]).map_err(|e| e.to_string())?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn update_step(&self, step: &StepExecution) -> Result<(), String> {
+        let update_sql = "UPDATE INDI_BATCH_STEP_EXECUTION SET END_TIME = ?1, STATUS = ?2, READ_COUNT = ?3, WRITE_COUNT = ?4, SKIP_COUNT = ?5, EXIT_MESSAGE = ?6, LAST_UPDATED = ?7 WHERE STEP_EXECUTION_ID = ?8";
+        self.conn.execute(update_sql, params![step.end_time.map(|t| t.to_rfc3339()), step.status.as_str(), step.read_count as i64, step.write_count as i64, step.skip_count as i64, step.exit_message, Utc::now().to_rfc3339(), step.id]).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
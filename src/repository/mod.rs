@@ -0,0 +1 @@
+pub mod job;
\ No newline at end of file
@@ -0,0 +1,2 @@
+pub mod table;
+pub mod vin;
\ No newline at end of file
@@ -0,0 +1,128 @@
+use std::{collections::HashMap, convert::TryFrom};
+use serde::Deserialize;
+
+use crate::config::settings::{lenient, ConfigError};
+use crate::routing::vin::VinCheck;
+
+pub static STRATEGIES: [&str; 3] = ["vin_lookup", "drop", "reject"];
+pub static MAX_CHUNK_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub enum RoutingStrategy {
+    Destination(String),
+    VinLookup,
+    Drop,
+    Reject,
+}
+
+impl TryFrom<String> for RoutingStrategy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "vin_lookup" => Ok(RoutingStrategy::VinLookup),
+            "drop" => Ok(RoutingStrategy::Drop),
+            "reject" => Ok(RoutingStrategy::Reject),
+            "" => Err(String::from("empty routing strategy")),
+            _ => Ok(RoutingStrategy::Destination(value)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingConfig {
+    #[serde(default = "default_strategy")]
+    pub default: RoutingStrategy,
+    #[serde(default)]
+    pub movements: HashMap<String, RoutingStrategy>,
+    #[serde(default)]
+    pub vin_lookup: VinLookupConfig,
+}
+
+// Destinations chosen by the vin_lookup strategy, and the strategy for VINs failing the check
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VinLookupConfig {
+    pub found: String,
+    pub missing: String,
+    #[serde(default = "default_vin_check")]
+    pub check: VinCheck,
+    #[serde(default = "default_invalid_strategy")]
+    pub invalid: RoutingStrategy,
+    // VINs per lookup query, Oracle IN lists take up to 1000 values
+    #[serde(default = "default_chunk_size", deserialize_with = "lenient")]
+    pub chunk_size: usize,
+}
+
+impl Default for VinLookupConfig {
+    fn default() -> Self {
+        VinLookupConfig {found: String::from("obt"), missing: String::from("legacy"), check: default_vin_check(), invalid: default_invalid_strategy(), chunk_size: default_chunk_size()}
+    }
+}
+
+fn default_vin_check() -> VinCheck {
+    VinCheck::Syntax
+}
+
+fn default_invalid_strategy() -> RoutingStrategy {
+    RoutingStrategy::Reject
+}
+
+fn default_chunk_size() -> usize {
+    MAX_CHUNK_SIZE
+}
+
+fn default_strategy() -> RoutingStrategy {
+    RoutingStrategy::Destination(String::from("legacy"))
+}
+
+impl RoutingConfig {
+    pub fn strategy(&self, movement_code: Option<&str>) -> &RoutingStrategy {
+        movement_code.and_then(|c| self.movements.get(c.trim())).unwrap_or(&self.default)
+    }
+
+    pub fn is_mapped(&self, movement_code: Option<&str>) -> bool {
+        movement_code.filter(|c| self.movements.contains_key(c.trim())).is_some()
+    }
+
+    pub fn validate(&self, key: &str, movement_code_length: usize, destinations: &[&str]) -> Result<(), ConfigError> {
+        validate_strategy(&format!("{}.default", key), &self.default, destinations)?;
+        for (code, strategy) in &self.movements {
+            let movement_key = format!("{}.movements.{}", key, code);
+            if code.trim().is_empty() || code.len() > movement_code_length {
+                return Err(ConfigError::Invalid(movement_key, format!("movement code must be 1 to {} characters long", movement_code_length)));
+            }
+            validate_strategy(&movement_key, strategy, destinations)?;
+        }
+        let uses_vin_lookup = self.default == RoutingStrategy::VinLookup || self.movements.values().any(|s| *s == RoutingStrategy::VinLookup);
+        if uses_vin_lookup {
+            validate_destination(&format!("{}.vin_lookup.found", key), &self.vin_lookup.found, destinations)?;
+            validate_destination(&format!("{}.vin_lookup.missing", key), &self.vin_lookup.missing, destinations)?;
+            let invalid_key = format!("{}.vin_lookup.invalid", key);
+            if self.vin_lookup.invalid == RoutingStrategy::VinLookup {
+                return Err(ConfigError::Invalid(invalid_key, String::from("invalid VINs cannot be looked up")));
+            }
+            validate_strategy(&invalid_key, &self.vin_lookup.invalid, destinations)?;
+            if self.vin_lookup.chunk_size == 0 || self.vin_lookup.chunk_size > MAX_CHUNK_SIZE {
+                return Err(ConfigError::Invalid(format!("{}.vin_lookup.chunk_size", key), format!("must be between 1 and {}", MAX_CHUNK_SIZE)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_strategy(key: &str, strategy: &RoutingStrategy, destinations: &[&str]) -> Result<(), ConfigError> {
+    match strategy {
+        RoutingStrategy::Destination(name) => validate_destination(key, name, destinations),
+        _ => Ok(()),
+    }
+}
+
+fn validate_destination(key: &str, name: &str, destinations: &[&str]) -> Result<(), ConfigError> {
+    if !destinations.contains(&name) {
+        return Err(ConfigError::Invalid(key.to_string(), format!("unknown routing strategy `{}`, expected one of {} or a destination ({})", name, STRATEGIES.join(", "), destinations.join(", "))));
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use serde::Deserialize;
+
+pub static VIN_LENGTH: usize = 17;
+// Position of the check digit, 0 based
+pub static CHECK_DIGIT_INDEX: usize = 8;
+static WEIGHTS: [u32; 17] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+// How strictly VINs are checked before the lookup, markets outside North America do not use the check digit
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VinCheck {
+    None,
+    Syntax,
+    CheckDigit,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VinError {
+    Blank,
+    Length(usize),
+    Character(usize, char),
+    CheckDigit(char, char),
+}
+
+impl fmt::Display for VinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VinError::Blank => write!(f, "VIN is blank"),
+            VinError::Length(length) => write!(f, "VIN has {} characters instead of {}", length, VIN_LENGTH),
+            VinError::Character(i, c) => write!(f, "VIN character {:?} at position {} is not allowed", c, i + 1),
+            VinError::CheckDigit(found, expected) => write!(f, "VIN check digit is {:?} instead of {:?}", found, expected),
+        }
+    }
+}
+
+pub fn validate(vin: &str, check: VinCheck) -> Result<(), VinError> {
+    if vin.trim().is_empty() {
+        return Err(VinError::Blank);
+    }
+    if check == VinCheck::None {
+        return Ok(());
+    }
+    let chars: Vec<char> = vin.chars().collect();
+    if chars.len() != VIN_LENGTH {
+        return Err(VinError::Length(chars.len()));
+    }
+    // ISO 3779 alphabet: digits and capital letters except I, O and Q
+    if let Some((i, c)) = chars.iter().enumerate().find(|(_, c)| transliterate(**c).is_none()) {
+        return Err(VinError::Character(i, *c));
+    }
+    if check == VinCheck::CheckDigit {
+        let expected = check_digit(&chars);
+        if chars[CHECK_DIGIT_INDEX] != expected {
+            return Err(VinError::CheckDigit(chars[CHECK_DIGIT_INDEX], expected));
+        }
+    }
+    Ok(())
+}
+
+fn check_digit(chars: &[char]) -> char {
+    let sum: u32 = chars.iter().zip(WEIGHTS.iter()).map(|(c, w)| transliterate(*c).unwrap() * w).sum();
+    match sum % 11 {
+        10 => 'X',
+        n => std::char::from_digit(n, 10).unwrap(),
+    }
+}
+
+fn transliterate(c: char) -> Option<u32> {
+    match c {
+        '0'..='9' => c.to_digit(10),
+        'A' | 'J' => Some(1),
+        'B' | 'K' | 'S' => Some(2),
+        'C' | 'L' | 'T' => Some(3),
+        'D' | 'M' | 'U' => Some(4),
+        'E' | 'N' | 'V' => Some(5),
+        'F' | 'W' => Some(6),
+        'G' | 'P' | 'X' => Some(7),
+        'H' | 'Y' => Some(8),
+        'R' | 'Z' => Some(9),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CHECKS: [VinCheck; 3] = [VinCheck::None, VinCheck::Syntax, VinCheck::CheckDigit];
+
+    #[test]
+    fn accepts_valid_vins() {
+        for check in CHECKS.iter() {
+            assert_eq!(validate("1M8GDM9AXKP042788", *check), Ok(()));
+            assert_eq!(validate("1HGCM82633A004352", *check), Ok(()));
+        }
+    }
+
+    #[test]
+    fn checks_the_check_digit() {
+        assert_eq!(validate("1M8GDM9A1KP042788", VinCheck::CheckDigit), Err(VinError::CheckDigit('1', 'X')));
+        assert_eq!(validate("1HGCM82643A004352", VinCheck::CheckDigit), Err(VinError::CheckDigit('4', '3')));
+        assert_eq!(validate("1M8GDM9A1KP042788", VinCheck::Syntax), Ok(()));
+    }
+
+    #[test]
+    fn uses_x_for_remainder_ten() {
+        let chars: Vec<char> = "1M8GDM9A0KP042788".chars().collect();
+        assert_eq!(check_digit(&chars), 'X');
+    }
+
+    #[test]
+    fn rejects_i_o_and_q() {
+        for (vin, c) in [("1M8GDM9AXKP04278I", 'I'), ("1M8GDM9AXKP04278O", 'O'), ("1M8GDM9AXKP04278Q", 'Q')].iter() {
+            assert_eq!(validate(vin, VinCheck::Syntax), Err(VinError::Character(16, *c)));
+            assert_eq!(validate(vin, VinCheck::CheckDigit), Err(VinError::Character(16, *c)));
+            assert_eq!(validate(vin, VinCheck::None), Ok(()));
+        }
+        assert_eq!(validate("1m8GDM9AXKP042788", VinCheck::Syntax), Err(VinError::Character(1, 'm')));
+    }
+
+    #[test]
+    fn rejects_blank_vins() {
+        for check in CHECKS.iter() {
+            assert_eq!(validate("", *check), Err(VinError::Blank));
+            assert_eq!(validate("    ", *check), Err(VinError::Blank));
+        }
+    }
+
+    #[test]
+    fn checks_the_length() {
+        assert_eq!(validate("1M8GDM9AXKP04278", VinCheck::None), Ok(()));
+        assert_eq!(validate("1M8GDM9AXKP04278", VinCheck::Syntax), Err(VinError::Length(16)));
+        assert_eq!(validate("1M8GDM9AXKP04278", VinCheck::CheckDigit), Err(VinError::Length(16)));
+        assert_eq!(validate("1M8GDM9AXKP0427880", VinCheck::CheckDigit), Err(VinError::Length(18)));
+    }
+}
@@ -1,8 +1,10 @@
-use std::{io::{BufReader, BufRead, Write, BufWriter}, path::PathBuf, fs::File};
+use std::{io::{BufReader, BufRead, Write, BufWriter}, path::Path, fs::File};
 
 use remotefs::{RemoteFs, RemoteError};
 use remotefs_ssh::{SftpFs, SshOpts};
 
+use crate::layout::terminator::LineTerminator;
+
 pub static DEFAULT_PORT: u16 = 22;
 
 pub struct Client {
@@ -23,7 +25,7 @@ impl SftpClient {
     }
 
     pub fn connect(&self) -> Result<SftpFs, RemoteError> {
-        let mut client: SftpFs = SshOpts::new(self.client.host.to_string()).port(self.client.port).username(self.client.usr.to_string()).password(self.client.pwd.to_string()).into();
+        let mut client: SftpFs = SshOpts::new(&self.client.host).port(self.client.port).username(&self.client.usr).password(&self.client.pwd).into();
         match client.connect() {
             Ok(_) => Ok(client),
             Err(e) => Err(e),
@@ -39,36 +41,50 @@ pub fn sftp_connect(host: String, port: u16, usr: String, pwd: String) -> SftpFs
     }
 }
 
-pub fn sftp_get(client: &mut SftpFs, remote_file: &remotefs::File, local_path: &PathBuf, prefix: String) {
+pub fn sftp_get(client: &mut SftpFs, remote_file: &remotefs::File, local_path: &Path, prefix: String, terminator: LineTerminator) {
     let is = client.open(remote_file.path()).unwrap();
     let mut reader = BufReader::new(is);
-    let mut buf = String::new();
-    let mut res = reader.read_line(&mut buf);
+    let mut buf = Vec::new();
+    let mut res = reader.read_until(b'\n', &mut buf);
     let filename = prefix + "_" + remote_file.name().as_str();
-    let mut final_path = local_path.clone();
+    let mut final_path = local_path.to_path_buf();
     final_path.push(filename);
     let mut file = File::create(&final_path).unwrap();
     while res.is_ok() && res.unwrap() > 0 {
-        file.write_all(buf.as_bytes()).unwrap();
+        file.write_all(&terminator.convert(&buf)).unwrap();
         buf.clear();
-        res = reader.read_line(&mut buf);
+        res = reader.read_until(b'\n', &mut buf);
     }
     println!("Downloaded file: {:?} -> {:?}", remote_file.path(), final_path);
 }
 
-pub fn sftp_put(client: &mut SftpFs, local_file: &PathBuf, remote_path: &PathBuf) {
+// Written under a temporary .<name>.part name and renamed once complete, so that an upload cut off and
+// repeated after a restart never leaves a partial or doubled file under the final name
+pub fn sftp_put(client: &mut SftpFs, local_file: &Path, remote_path: &Path, terminator: LineTerminator) {
     let file = File::open(local_file).unwrap();
     let metadata = remotefs::fs::Metadata::from(file.metadata().unwrap());
-    let ws = client.append(remote_path, &metadata).unwrap();
+    let part_name = String::from(".") + remote_path.file_name().unwrap().to_str().unwrap() + ".part";
+    let part_path = remote_path.with_file_name(part_name);
+    let ws = client.create(&part_path, &metadata).unwrap();
     let mut writer = BufWriter::new(ws);
     let mut reader = BufReader::new(file);
-    let mut buf = String::new();
-    let mut res = reader.read_line(&mut buf);
+    let mut buf = Vec::new();
+    let mut res = reader.read_until(b'\n', &mut buf);
     while res.is_ok() && res.unwrap() > 0 {
-        writer.write_all(buf.as_bytes()).unwrap();
+        writer.write_all(&terminator.convert(&buf)).unwrap();
         buf.clear();
-        res = reader.read_line(&mut buf);
+        res = reader.read_until(b'\n', &mut buf);
+    }
+    let ws = match writer.into_inner() {
+        Ok(ws) => ws,
+        Err(e) => panic!("Cannot upload {:?}! {}", part_path, e.error()),
+    };
+    client.on_written(ws).unwrap();
+    // A file delivered before a crash is replaced, SFTP rename does not overwrite
+    if client.remove_file(remote_path).is_ok() {
+        println!("Replaced remote file: {:?}", remote_path);
     }
+    client.mov(&part_path, remote_path).unwrap();
 }
 
 pub fn sftp_rm(client: &mut SftpFs, remote_file: &remotefs::File) {
@@ -1,31 +1,140 @@
+use std::{cell::RefCell, sync::mpsc, thread, time::Duration};
 use oracle::{Connection, Error};
 
+use crate::config::settings::DatabaseConfig;
+
+// ORA codes meaning the session is gone and a new connection is needed
+static CONNECTION_LOST_CODES: [i32; 17] = [28, 1012, 1033, 1034, 1089, 2396, 3113, 3114, 3135, 12152, 12153, 12514, 12537, 12541, 12547, 12571, 25408];
+// ODPI-C codes for a closed or missing connection
+static CONNECTION_LOST_DPI: [&str; 2] = ["DPI-1010", "DPI-1080"];
+// Calls are given up after 4 attempts, 1 + 2 + 4 seconds apart
+static MAX_ATTEMPTS: u32 = 4;
+static RETRY_DELAY: Duration = Duration::from_secs(1);
+
 pub struct Client {
     usr: String,
     pwd: String,
     url: String,
+    pool_size: usize,
+    connect_timeout: Duration,
+    call_timeout: Duration,
 }
 
+// Small pool of connections checked with a ping before use and replaced when lost
 pub struct OracleClient {
     client: Client,
+    pool: RefCell<Vec<Connection>>,
 }
 
 impl OracleClient {
-    pub fn new<S: AsRef<str>>(usr: S, pwd: S, url: S) -> OracleClient {
-        OracleClient {client: Client {usr: usr.as_ref().to_string(), pwd: pwd.as_ref().to_string(), url: url.as_ref().to_string()}}
+    pub fn new(config: &DatabaseConfig) -> OracleClient {
+        let client = Client {
+            usr: config.username.to_string(),
+            pwd: config.password.to_string(),
+            url: config.url.to_string(),
+            pool_size: config.pool_size,
+            connect_timeout: Duration::from_secs(config.connect_timeout),
+            call_timeout: Duration::from_secs(config.call_timeout),
+        };
+        OracleClient {client, pool: RefCell::new(Vec::new())}
     }
 
     pub fn connect(&self) -> Result<Connection, Error> {
-        Connection::connect(self.client.usr.to_string(), self.client.pwd.to_string(), self.client.url.to_string())
+        let (usr, pwd, url) = (self.client.usr.to_string(), self.client.pwd.to_string(), self.client.url.to_string());
+        let conn = if self.client.connect_timeout.is_zero() {
+            Connection::connect(usr, pwd, url)?
+        } else {
+            // The connect call cannot be interrupted, a timed out attempt is left to finish in background
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send(Connection::connect(usr, pwd, url));
+            });
+            match rx.recv_timeout(self.client.connect_timeout) {
+                Ok(conn) => conn?,
+                Err(_) => return Err(Error::InternalError(format!("connection to {} timed out after {:?}", self.client.url, self.client.connect_timeout))),
+            }
+        };
+        if !self.client.call_timeout.is_zero() {
+            conn.set_call_timeout(Some(self.client.call_timeout))?;
+        }
+        println!("Connected to {}", self.client.url);
+        Ok(conn)
+    }
+
+    // Runs f on a pooled connection; when no connection can be made or the session turns out to be lost,
+    // f is run again on a new connection after an increasing delay, up to MAX_ATTEMPTS times.
+    // Uncommitted work of a lost session is rolled back by the server, so f must do its own commit
+    pub fn execute<T, F>(&self, mut f: F) -> Result<T, Error> where F: FnMut(&Connection) -> Result<T, Error> {
+        let mut attempt = 1;
+        loop {
+            let (res, retry) = match self.checkout() {
+                Ok(conn) => {
+                    let res = f(&conn);
+                    let lost = matches!(&res, Err(e) if is_connection_lost(e));
+                    if lost {
+                        let _ = conn.close();
+                    } else {
+                        self.checkin(conn);
+                    }
+                    (res, lost)
+                },
+                Err(e) => (Err(e), true),
+            };
+            match res {
+                Err(e) if retry && attempt < MAX_ATTEMPTS => {
+                    let delay = RETRY_DELAY * 2u32.pow(attempt - 1);
+                    println!("Database {} unavailable: {}, retrying in {:?} ({}/{})", self.client.url, e, delay, attempt, MAX_ATTEMPTS - 1);
+                    thread::sleep(delay);
+                    attempt += 1;
+                },
+                res => return res,
+            }
+        }
+    }
+
+    // Runs f through execute, committing when it succeeds and rolling back otherwise
+    pub fn transaction<T, F>(&self, mut f: F) -> Result<T, Error> where F: FnMut(&Connection) -> Result<T, Error> {
+        self.execute(|conn| match f(conn) {
+            Ok(value) => {
+                conn.commit()?;
+                Ok(value)
+            },
+            Err(e) => {
+                if let Err(rollback) = conn.rollback() {
+                    println!("Cannot roll back on {}: {}", self.client.url, rollback);
+                }
+                Err(e)
+            },
+        })
+    }
+
+    fn checkout(&self) -> Result<Connection, Error> {
+        loop {
+            let pooled = self.pool.borrow_mut().pop();
+            match pooled {
+                Some(conn) => match conn.ping() {
+                    Ok(()) => return Ok(conn),
+                    Err(e) => println!("Dropped unhealthy connection to {}: {}", self.client.url, e),
+                },
+                None => return self.connect(),
+            }
+        }
     }
-}
 
-pub fn currval_sequence(conn: &Connection, schema: &str, sequence: String) -> Result<String, Error> {
-    let currval_sql = "SELECT LPAD(last_number, 5, '0') FROM all_sequences WHERE sequence_owner = :schema AND sequence_name = :sequence";
-    conn.query_row_as_named::<String>(currval_sql, &[("schema", &schema), ("sequence", &sequence)])
+    fn checkin(&self, conn: Connection) {
+        let mut pool = self.pool.borrow_mut();
+        if pool.len() < self.client.pool_size {
+            pool.push(conn);
+        } else if let Err(e) = conn.close() {
+            println!("Cannot close connection to {}: {}", self.client.url, e);
+        }
+    }
 }
 
-pub fn nextval_sequence(conn: &Connection, schema: &str, sequence: String) -> Result<String, Error> {
-    let nextval_sql = String::from("SELECT ") + schema + "." + &sequence + ".nextval FROM DUAL";
-    conn.query_row_as::<String>(&nextval_sql, &[])
-}
\ No newline at end of file
+pub fn is_connection_lost(e: &Error) -> bool {
+    match e {
+        Error::OciError(db) => CONNECTION_LOST_CODES.contains(&db.code()),
+        Error::DpiError(db) => CONNECTION_LOST_DPI.iter().any(|c| db.message().starts_with(c)),
+        _ => false,
+    }
+}
@@ -0,0 +1,128 @@
+use oracle::{Connection, Error};
+use regex::Regex;
+
+// Destination sequence values are tracked in INDI_SEQUENCE_RESERVATION (see sql/indi_sequence_reservation.sql):
+// a value is RESERVED for a file before splitting, RELEASED when its output is not delivered and taken again
+// by the next reservation, DELIVERED once the output is uploaded or inserted
+pub static RESERVED: &str = "RESERVED";
+pub static RELEASED: &str = "RELEASED";
+pub static DELIVERED: &str = "DELIVERED";
+
+// INDI_<system>_<file name without the last _ part>_<DESTINATION>_SEQ
+pub fn sequence_name(system: &str, filename: &str, destination: &str) -> String {
+    let prefix = filename.rsplit_once('_').map(|(p, _)| p).unwrap_or(filename);
+    String::from("INDI") + "_" + system + "_" + prefix + "_" + destination.to_uppercase().as_str() + "_SEQ"
+}
+
+// Unquoted Oracle identifier, schema and sequence names are concatenated into the NEXTVAL statement
+pub fn is_identifier(value: &str) -> bool {
+    Regex::new(r"^[A-Z][A-Z0-9_$#]{0,127}$").unwrap().is_match(value)
+}
+
+// Reason why schema.sequence cannot be used, None when it is a valid and existing sequence
+pub fn missing_sequence(conn: &Connection, schema: &str, sequence: &str) -> Result<Option<String>, Error> {
+    if !is_identifier(schema) {
+        return Ok(Some(format!("invalid sequence schema {:?}", schema)));
+    }
+    if !is_identifier(sequence) {
+        return Ok(Some(format!("invalid sequence name {:?}, check the source file name", sequence)));
+    }
+    let exists_sql = "SELECT COUNT(*) FROM all_sequences WHERE sequence_owner = :schema AND sequence_name = :sequence";
+    if conn.query_row_as_named::<u64>(exists_sql, &[("schema", &schema), ("sequence", &sequence)])? == 0 {
+        return Ok(Some(format!("sequence {}.{} does not exist, it must be created before processing this source", schema, sequence)));
+    }
+    Ok(None)
+}
+
+// Value the next NEXTVAL should return, without consuming it: last_number is the end of the cached range of a cached
+// sequence, so the value after the last reserved one is taken while it is inside that range
+fn peek_nextval(conn: &Connection, schema: &str, sequence: &str) -> Result<u64, Error> {
+    let peek_sql = "SELECT s.last_number, s.increment_by, (SELECT MAX(r.SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION r WHERE r.SEQUENCE_NAME = :sequence) FROM all_sequences s WHERE s.sequence_owner = :schema AND s.sequence_name = :sequence";
+    let (last_number, increment, reserved) = conn.query_row_as_named::<(u64, u64, Option<u64>)>(peek_sql, &[("schema", &schema), ("sequence", &sequence)])?;
+    match reserved {
+        Some(value) if value + increment < last_number => Ok(value + increment),
+        _ => Ok(last_number),
+    }
+}
+
+// Value reserve would take for the file, read only so that dry runs neither lock nor consume values
+pub fn preview(conn: &Connection, schema: &str, sequence: &str, file: &str) -> Result<u64, Error> {
+    let reserved_sql = "SELECT MIN(SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND FILE_NAME = :file AND STATUS = :status";
+    if let Some(value) = conn.query_row_as_named::<Option<u64>>(reserved_sql, &[("sequence", &sequence), ("file", &file), ("status", &RESERVED)])? {
+        return Ok(value);
+    }
+    let released_sql = "SELECT MIN(SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND STATUS = :status";
+    if let Some(value) = conn.query_row_as_named::<Option<u64>>(released_sql, &[("sequence", &sequence), ("status", &RELEASED)])? {
+        return Ok(value);
+    }
+    peek_nextval(conn, schema, sequence)
+}
+
+fn nextval_sequence(conn: &Connection, schema: &str, sequence: &str) -> Result<u64, Error> {
+    if !is_identifier(schema) || !is_identifier(sequence) {
+        return Err(Error::InternalError(format!("invalid sequence identifier {}.{}", schema, sequence)));
+    }
+    let nextval_sql = String::from("SELECT ") + schema + "." + sequence + ".nextval FROM DUAL";
+    conn.query_row_as::<u64>(&nextval_sql, &[])
+}
+
+// Takes the value already reserved for the file by an interrupted run, else the lowest released one, else NEXTVAL;
+// must run in a transaction, the selected rows stay locked until commit
+pub fn reserve(conn: &Connection, schema: &str, sequence: &str, file: &str) -> Result<u64, Error> {
+    let reserved_sql = "SELECT MIN(SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND FILE_NAME = :file AND STATUS = :status";
+    if let Some(value) = conn.query_row_as_named::<Option<u64>>(reserved_sql, &[("sequence", &sequence), ("file", &file), ("status", &RESERVED)])? {
+        println!("Resumed {} reservation {} for {}", sequence, value, file);
+        return Ok(value);
+    }
+    let released_sql = "SELECT SEQUENCE_VALUE FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND STATUS = :status ORDER BY SEQUENCE_VALUE FOR UPDATE SKIP LOCKED";
+    let released = conn.query_as_named::<u64>(released_sql, &[("sequence", &sequence), ("status", &RELEASED)])?.next().transpose()?;
+    let value = match released {
+        Some(value) => {
+            let update_sql = "UPDATE INDI_SEQUENCE_RESERVATION SET STATUS = :status, FILE_NAME = :file, RESERVED_AT = SYSTIMESTAMP WHERE SEQUENCE_NAME = :sequence AND SEQUENCE_VALUE = :value";
+            conn.execute_named(update_sql, &[("status", &RESERVED), ("file", &file), ("sequence", &sequence), ("value", &value)])?;
+            value
+        },
+        None => {
+            let value = nextval_sequence(conn, schema, sequence)?;
+            let insert_sql = "INSERT INTO INDI_SEQUENCE_RESERVATION (SEQUENCE_NAME, SEQUENCE_VALUE, FILE_NAME, STATUS, RESERVED_AT) VALUES (:sequence, :value, :file, :status, SYSTIMESTAMP)";
+            conn.execute_named(insert_sql, &[("sequence", &sequence), ("value", &value), ("file", &file), ("status", &RESERVED)])?;
+            value
+        },
+    };
+    println!("Reserved {} value {} for {}", sequence, value, file);
+    Ok(value)
+}
+
+// Only frees the value while it is still reserved for the file, a release repeated after a restart
+// must not free the value once another file took it
+pub fn release(conn: &Connection, sequence: &str, value: u64, file: &str) -> Result<(), Error> {
+    let release_sql = "UPDATE INDI_SEQUENCE_RESERVATION SET STATUS = :released, FILE_NAME = NULL WHERE SEQUENCE_NAME = :sequence AND SEQUENCE_VALUE = :value AND STATUS = :reserved AND FILE_NAME = :file";
+    let stmt = conn.execute_named(release_sql, &[("released", &RELEASED), ("sequence", &sequence), ("value", &value), ("reserved", &RESERVED), ("file", &file)])?;
+    if stmt.row_count()? > 0 {
+        println!("Released {} value {}", sequence, value);
+    }
+    Ok(())
+}
+
+// A value is still reserved for the file, its output is not delivered yet
+pub fn is_reserved(conn: &Connection, sequence: &str, file: &str) -> Result<bool, Error> {
+    let reserved_sql = "SELECT COUNT(*) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND FILE_NAME = :file AND STATUS = :status";
+    Ok(conn.query_row_as_named::<u64>(reserved_sql, &[("sequence", &sequence), ("file", &file), ("status", &RESERVED)])? > 0)
+}
+
+// Value reserved for the file, or the last one delivered for it when the delivery was already confirmed
+pub fn file_value(conn: &Connection, sequence: &str, file: &str) -> Result<Option<u64>, Error> {
+    let reserved_sql = "SELECT MIN(SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND FILE_NAME = :file AND STATUS = :status";
+    if let Some(value) = conn.query_row_as_named::<Option<u64>>(reserved_sql, &[("sequence", &sequence), ("file", &file), ("status", &RESERVED)])? {
+        return Ok(Some(value));
+    }
+    let delivered_sql = "SELECT MAX(SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND FILE_NAME = :file AND STATUS = :status";
+    conn.query_row_as_named::<Option<u64>>(delivered_sql, &[("sequence", &sequence), ("file", &file), ("status", &DELIVERED)])
+}
+
+// Marks the oldest value reserved for the file as delivered, returns false when there is none
+pub fn confirm(conn: &Connection, sequence: &str, file: &str) -> Result<bool, Error> {
+    let confirm_sql = "UPDATE INDI_SEQUENCE_RESERVATION SET STATUS = :delivered, DELIVERED_AT = SYSTIMESTAMP WHERE SEQUENCE_NAME = :sequence AND STATUS = :reserved AND SEQUENCE_VALUE = (SELECT MIN(SEQUENCE_VALUE) FROM INDI_SEQUENCE_RESERVATION WHERE SEQUENCE_NAME = :sequence AND FILE_NAME = :file AND STATUS = :reserved)";
+    let stmt = conn.execute_named(confirm_sql, &[("delivered", &DELIVERED), ("sequence", &sequence), ("reserved", &RESERVED), ("file", &file)])?;
+    Ok(stmt.row_count()? > 0)
+}